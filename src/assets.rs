@@ -1,55 +1,481 @@
-use bevy::{ecs::system::SystemParam, gltf::Gltf, prelude::*};
+//! The glTF libraries the levels are built from. A manifest lists each library with the scenes it
+//! has to provide, and everything is checked before [`AssetState::Loaded`]. It lists the
+//! [levels](crate::levels) too.
+
+use std::{collections::BTreeMap, fmt};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext, LoadState},
+    ecs::system::SystemParam,
+    gltf::Gltf,
+    prelude::*,
+    utils::{BoxedFuture, HashMap},
+};
 use bevy_gltf_components::{process_loaded_scenes, track_new_gltf, ComponentsFromGltfPlugin};
+use serde::Deserialize;
+
+use crate::levels::LevelDescription;
 
 pub struct AssetsPlugin;
 
 impl Plugin for AssetsPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(ComponentsFromGltfPlugin)
+            .init_asset::<AssetManifest>()
+            .init_asset_loader::<AssetManifestLoader>()
             .add_state::<AssetState>()
-            .add_systems(Startup, load_gltf)
-            .add_systems(Update, check_loaded.run_if(in_state(AssetState::Loading)))
+            .add_systems(Startup, load_manifest)
+            .add_systems(Update, reload_modified_manifest)
+            .add_systems(
+                Update,
+                (load_libraries, check_loaded)
+                    .chain()
+                    .run_if(in_state(AssetState::Loading)),
+            )
             .add_systems(
                 OnExit(AssetState::Loading),
                 (track_new_gltf, process_loaded_scenes).chain(),
-            );
+            )
+            .add_systems(OnEnter(AssetState::Failed), report_failure);
     }
 }
 
 #[derive(States, Clone, Eq, PartialEq, Debug, Default, Hash)]
 pub enum AssetState {
+    /// Loading the manifest and the libraries it lists, again whenever the manifest changes.
     #[default]
     Loading,
     Loaded,
+    /// Something in the manifest couldn't be loaded, see [`AssetLibError`].
+    Failed,
+}
+
+/// The glTF libraries to load, by name, and the levels built from them.
+#[derive(Asset, TypePath, Deserialize, Debug, Clone)]
+pub struct AssetManifest {
+    pub libraries: BTreeMap<String, LibraryDescription>,
+    /// The first one starts once the libraries have loaded.
+    #[serde(default)]
+    pub levels: Vec<LevelDescription>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LibraryDescription {
+    pub path: String,
+    /// Named scenes the library has to have. Loading fails if any are missing.
+    #[serde(default)]
+    pub scenes: Vec<String>,
+}
+
+/// Why the asset libraries couldn't be loaded, or a scene couldn't be found. Inserted as a
+/// resource on entering [`AssetState::Failed`].
+#[derive(Resource, Debug, Clone, PartialEq, Eq)]
+pub enum AssetLibError {
+    Manifest {
+        path: String,
+    },
+    Library {
+        library: String,
+        path: String,
+    },
+    UnknownLibrary {
+        library: String,
+    },
+    MissingScene {
+        library: String,
+        scene: String,
+    },
+    /// The libraries are still loading.
+    NotLoaded,
 }
 
+impl fmt::Display for AssetLibError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetLibError::Manifest { path } => write!(f, "could not load manifest {path}"),
+            AssetLibError::Library { library, path } => {
+                write!(f, "could not load library {library} from {path}")
+            }
+            AssetLibError::UnknownLibrary { library } => {
+                write!(f, "no library named {library} in the manifest")
+            }
+            AssetLibError::MissingScene { library, scene } => {
+                write!(f, "library {library} has no scene named {scene}")
+            }
+            AssetLibError::NotLoaded => write!(f, "asset libraries are not loaded yet"),
+        }
+    }
+}
+
+impl std::error::Error for AssetLibError {}
+
+const MANIFEST_PATH: &str = "main.manifest.ron";
+
 #[derive(Resource)]
-struct AssetLibHandle(Handle<Gltf>);
+struct AssetLibHandles {
+    manifest: Handle<AssetManifest>,
+    libraries: HashMap<String, Handle<Gltf>>,
+}
 
 #[derive(SystemParam)]
 pub struct AssetLib<'w> {
-    handle: Res<'w, AssetLibHandle>,
-    asset_server: Res<'w, Assets<Gltf>>,
+    handles: Res<'w, AssetLibHandles>,
+    manifests: Res<'w, Assets<AssetManifest>>,
+    gltfs: Res<'w, Assets<Gltf>>,
 }
 
 impl AssetLib<'_> {
-    pub fn scene(&self, name: &str) -> Handle<Scene> {
-        let gltf = self.asset_server.get(self.handle.0.clone()).unwrap();
-        gltf.named_scenes[name].clone()
+    /// The scene `name` from the library `library`.
+    pub fn scene(&self, library: &str, name: &str) -> Result<Handle<Scene>, AssetLibError> {
+        let handle =
+            self.handles
+                .libraries
+                .get(library)
+                .ok_or_else(|| AssetLibError::UnknownLibrary {
+                    library: library.to_string(),
+                })?;
+        let gltf = self.gltfs.get(handle).ok_or(AssetLibError::NotLoaded)?;
+        gltf.named_scenes
+            .get(name)
+            .cloned()
+            .ok_or_else(|| AssetLibError::MissingScene {
+                library: library.to_string(),
+                scene: name.to_string(),
+            })
+    }
+
+    pub fn manifest(&self) -> Option<&AssetManifest> {
+        self.manifests.get(&self.handles.manifest)
     }
 
     pub fn is_loaded(&self) -> bool {
-        self.asset_server.get(self.handle.0.clone()).is_some()
+        let Some(manifest) = self.manifest() else {
+            return false;
+        };
+        self.handles.libraries.len() == manifest.libraries.len()
+            && self
+                .handles
+                .libraries
+                .values()
+                .all(|handle| self.gltfs.contains(handle))
+    }
+}
+
+fn load_manifest(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(AssetLibHandles {
+        manifest: asset_server.load(MANIFEST_PATH),
+        libraries: HashMap::default(),
+    });
+}
+
+// Start loading the libraries once the manifest says what they are
+fn load_libraries(
+    mut handles: ResMut<AssetLibHandles>,
+    manifests: Res<Assets<AssetManifest>>,
+    asset_server: Res<AssetServer>,
+) {
+    let Some(manifest) = manifests.get(&handles.manifest) else {
+        return;
+    };
+    if handles.libraries.len() == manifest.libraries.len() {
+        return;
+    }
+    for (name, library) in manifest.libraries.iter() {
+        info!("Loading library {} from {}", name, library.path);
+        let handle = asset_server.load(&library.path);
+        handles.libraries.insert(name.clone(), handle);
+    }
+}
+
+fn check_loaded(
+    mut commands: Commands,
+    assets: AssetLib,
+    asset_server: Res<AssetServer>,
+    mut asset_state: ResMut<NextState<AssetState>>,
+) {
+    if asset_server.get_load_state(assets.handles.manifest.id()) == Some(LoadState::Failed) {
+        commands.insert_resource(AssetLibError::Manifest {
+            path: MANIFEST_PATH.to_string(),
+        });
+        asset_state.set(AssetState::Failed);
+        return;
+    }
+    let Some(manifest) = assets.manifest() else {
+        return;
+    };
+
+    for (name, handle) in assets.handles.libraries.iter() {
+        if asset_server.get_load_state(handle.id()) == Some(LoadState::Failed) {
+            commands.insert_resource(AssetLibError::Library {
+                library: name.clone(),
+                path: manifest.libraries[name].path.clone(),
+            });
+            asset_state.set(AssetState::Failed);
+            return;
+        }
+    }
+    if !assets.is_loaded() {
+        return;
+    }
+
+    for (name, library) in manifest.libraries.iter() {
+        for scene in library.scenes.iter() {
+            if let Err(error) = assets.scene(name, scene) {
+                commands.insert_resource(error);
+                asset_state.set(AssetState::Failed);
+                return;
+            }
+        }
+    }
+    asset_state.set(AssetState::Loaded);
+}
+
+fn report_failure(error: Res<AssetLibError>) {
+    error!("Could not load assets: {}", *error);
+}
+
+// The libraries may have changed with the manifest, so load them all again
+fn reload_modified_manifest(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<AssetManifest>>,
+    mut handles: ResMut<AssetLibHandles>,
+    mut asset_state: ResMut<NextState<AssetState>>,
+) {
+    let modified = asset_events
+        .read()
+        .filter(
+            |event| matches!(event, AssetEvent::Modified { id } if *id == handles.manifest.id()),
+        )
+        .count()
+        > 0;
+    if !modified {
+        return;
+    }
+    info!("Manifest changed, reloading asset libraries");
+    handles.libraries.clear();
+    commands.remove_resource::<AssetLibError>();
+    asset_state.set(AssetState::Loading);
+}
+
+#[derive(Default)]
+pub struct AssetManifestLoader;
+
+#[derive(Debug)]
+pub enum AssetManifestLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl fmt::Display for AssetManifestLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetManifestLoaderError::Io(error) => write!(f, "could not read manifest: {error}"),
+            AssetManifestLoaderError::Ron(error) => {
+                write!(f, "could not parse manifest: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssetManifestLoaderError {}
+
+impl From<std::io::Error> for AssetManifestLoaderError {
+    fn from(error: std::io::Error) -> Self {
+        AssetManifestLoaderError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for AssetManifestLoaderError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        AssetManifestLoaderError::Ron(error)
     }
 }
 
-fn load_gltf(mut commands: Commands, ass: Res<AssetServer>) {
-    let gltf = ass.load("gen/test.gltf");
-    commands.insert_resource(AssetLibHandle(gltf));
+impl AssetLoader for AssetManifestLoader {
+    type Asset = AssetManifest;
+    type Settings = ();
+    type Error = AssetManifestLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<AssetManifest, AssetManifestLoaderError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["manifest.ron"]
+    }
 }
 
-fn check_loaded(assets: AssetLib, mut asset_state: ResMut<NextState<AssetState>>) {
-    if assets.is_loaded() {
-        asset_state.set(AssetState::Loaded);
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, thread, time::Duration};
+
+    use bevy::{
+        asset::AssetPlugin, ecs::system::SystemState, gltf::GltfPlugin, scene::ScenePlugin,
+    };
+
+    use super::*;
+
+    // A library with a single, empty scene named "ship"
+    const LIBRARY: &str =
+        r#"{"asset":{"version":"2.0"},"scenes":[{"name":"ship","nodes":[]}],"scene":0}"#;
+
+    fn asset_dir(name: &str, manifest: Option<&str>) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("test.gltf"), LIBRARY).unwrap();
+        if let Some(manifest) = manifest {
+            fs::write(dir.join(MANIFEST_PATH), manifest).unwrap();
+        }
+        dir
+    }
+
+    // Runs the app until the libraries are loaded or have failed to
+    fn load(dir: PathBuf) -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                file_path: dir.to_string_lossy().into_owned(),
+                ..default()
+            },
+            ScenePlugin,
+            GltfPlugin::default(),
+        ))
+        .init_asset::<Mesh>()
+        .init_asset::<StandardMaterial>()
+        .init_asset::<Image>()
+        .add_plugins(AssetsPlugin);
+        // The glTF loader is only added once the plugins finish
+        app.finish();
+        app.cleanup();
+        settle(&mut app);
+        app
+    }
+
+    fn settle(app: &mut App) {
+        for _ in 0..500 {
+            app.update();
+            if *app.world.resource::<State<AssetState>>() != AssetState::Loading {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("still loading");
+    }
+
+    fn error(app: &App) -> Option<&AssetLibError> {
+        app.world.get_resource::<AssetLibError>()
+    }
+
+    #[test]
+    fn loads_required_scenes() {
+        let manifest = r#"(libraries: {"test": (path: "test.gltf", scenes: ["ship"])})"#;
+        let app = load(asset_dir("loads", Some(manifest)));
+        assert_eq!(
+            *app.world.resource::<State<AssetState>>(),
+            AssetState::Loaded
+        );
+    }
+
+    #[test]
+    fn fails_without_manifest() {
+        let app = load(asset_dir("no-manifest", None));
+        assert_eq!(
+            error(&app),
+            Some(&AssetLibError::Manifest {
+                path: MANIFEST_PATH.to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn fails_on_missing_library_file() {
+        let manifest = r#"(libraries: {"test": (path: "missing.gltf")})"#;
+        let app = load(asset_dir("missing-file", Some(manifest)));
+        assert_eq!(
+            error(&app),
+            Some(&AssetLibError::Library {
+                library: "test".to_string(),
+                path: "missing.gltf".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn fails_on_missing_scene() {
+        let manifest = r#"(libraries: {"test": (path: "test.gltf", scenes: ["ship", "hamster"])})"#;
+        let app = load(asset_dir("missing-scene", Some(manifest)));
+        assert_eq!(
+            error(&app),
+            Some(&AssetLibError::MissingScene {
+                library: "test".to_string(),
+                scene: "hamster".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn reports_unknown_library() {
+        let manifest = r#"(libraries: {"test": (path: "test.gltf")})"#;
+        let mut app = load(asset_dir("unknown-library", Some(manifest)));
+        let mut state: SystemState<AssetLib> = SystemState::new(&mut app.world);
+        let assets = state.get(&app.world);
+        assert!(assets.scene("test", "ship").is_ok());
+        assert_eq!(
+            assets.scene("other", "ship"),
+            Err(AssetLibError::UnknownLibrary {
+                library: "other".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn reloads_libraries_when_manifest_changes() {
+        let manifest = r#"(libraries: {"test": (path: "test.gltf")})"#;
+        let mut app = load(asset_dir("reload", Some(manifest)));
+
+        let handle = app.world.resource::<AssetLibHandles>().manifest.clone();
+        let mut manifests = app.world.resource_mut::<Assets<AssetManifest>>();
+        let libraries = &mut manifests.get_mut(&handle).unwrap().libraries;
+        let library = libraries.remove("test").unwrap();
+        libraries.insert("renamed".to_string(), library);
+        // One update to send the change, and one to see it
+        app.update();
+        app.update();
+        settle(&mut app);
+
+        let mut state: SystemState<AssetLib> = SystemState::new(&mut app.world);
+        let assets = state.get(&app.world);
+        assert!(assets.scene("renamed", "ship").is_ok());
+        assert!(assets.scene("test", "ship").is_err());
+    }
+
+    #[test]
+    fn reads_levels_from_the_shipped_manifest() {
+        let manifest: AssetManifest =
+            ron::de::from_str(include_str!("../assets/main.manifest.ron")).unwrap();
+        let level = manifest.levels[0].level();
+        assert_eq!(level.name, "test");
+        assert_eq!(level.description.as_deref(), Some("main.scene.ron"));
+        let scenes: Vec<&str> = level
+            .scenes
+            .iter()
+            .map(|scene| scene.scene.as_str())
+            .collect();
+        assert_eq!(scenes, ["ship", "hamster", "asteroid"]);
+        assert!(level.scenes[0].fallback_physics.is_none());
+        let asteroid = level.scenes[2].fallback_physics.unwrap();
+        assert_eq!(asteroid.body.density, 4000.0);
+        assert!(asteroid.grabbable);
     }
 }
@@ -0,0 +1,347 @@
+//! Recovering grabbable objects that end up somewhere the player can't reach them again, like
+//! below the ground or floating off into space.
+
+use bevy::{
+    ecs::system::{Command, SystemState},
+    hierarchy::despawn_with_children_recursive,
+    prelude::*,
+    transform::TransformSystem,
+};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    body_of,
+    construction::connector::{Connection, Connector},
+    sockets::{socket_into, Anchor, Socketed},
+    vr_hands::{
+        detach::{detach_body, parts_of},
+        grabber::{held_bodies, Grabbable, Grabber},
+    },
+};
+
+pub struct BoundsPlugin;
+
+impl Plugin for BoundsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<KillVolume>()
+            .register_type::<OutOfBoundsBehavior>()
+            .init_resource::<Bounds>()
+            .add_event::<OutOfBoundsEvent>()
+            .add_systems(
+                Update,
+                (
+                    remember_sockets,
+                    find_out_of_bounds_bodies,
+                    recover_out_of_bounds_bodies,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                PostUpdate,
+                record_spawn_points.after(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// Where grabbable bodies count as out of bounds, on top of any [`KillVolume`]s.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Bounds {
+    /// Bodies below this height are out of bounds.
+    pub kill_height: f32,
+    /// Bodies further than this from the origin are out of bounds.
+    pub max_distance: f32,
+    /// The behavior of bodies without an [`OutOfBoundsBehavior`].
+    pub default_behavior: OutOfBoundsBehavior,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Self {
+            kill_height: -5.0,
+            max_distance: 20.0,
+            default_behavior: OutOfBoundsBehavior::RespawnAtOrigin,
+        }
+    }
+}
+
+/// A box that grabbable bodies are out of bounds in, centered on the entity.
+#[derive(Component, Reflect, Default, Debug, Clone, Copy)]
+#[reflect(Component)]
+pub struct KillVolume {
+    pub half_extents: Vec3,
+}
+
+/// What happens to a grabbable body once it's out of bounds.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(Component)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutOfBoundsBehavior {
+    Despawn,
+    /// Move back to where the body first appeared.
+    #[default]
+    RespawnAtOrigin,
+    /// Snap back into the last anchor the body was socketed in, if it's still free. Otherwise
+    /// respawn at the origin.
+    ReturnToLastSocket,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct OutOfBoundsEvent {
+    pub body: Entity,
+}
+
+/// The global pose a body first appeared at.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct SpawnPoint(pub Transform);
+
+/// The anchor an [`Anchorable`](crate::sockets::Anchorable) was last socketed in.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct LastSocket {
+    pub anchor: Entity,
+}
+
+#[allow(clippy::type_complexity)]
+fn record_spawn_points(
+    mut commands: Commands,
+    bodies: Query<
+        (Entity, &GlobalTransform),
+        (With<Grabbable>, With<RigidBody>, Without<SpawnPoint>),
+    >,
+) {
+    for (entity, transform) in bodies.iter() {
+        commands
+            .entity(entity)
+            .insert(SpawnPoint(transform.compute_transform()));
+    }
+}
+
+fn remember_sockets(mut commands: Commands, socketed: Query<(Entity, &Socketed), Added<Socketed>>) {
+    for (anchorable, socketed) in socketed.iter() {
+        commands.entity(anchorable).insert(LastSocket {
+            anchor: socketed.anchor,
+        });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn find_out_of_bounds_bodies(
+    bounds: Res<Bounds>,
+    bodies: Query<(Entity, &GlobalTransform), (With<Grabbable>, With<RigidBody>)>,
+    kill_volumes: Query<(&KillVolume, &GlobalTransform)>,
+    grabbers: Query<&Grabber>,
+    mut out_of_bounds_events: EventWriter<OutOfBoundsEvent>,
+) {
+    // A held body goes wherever the hand goes
+    let held = held_bodies(&grabbers);
+    for (body, transform) in bodies.iter() {
+        if held.contains(&body) {
+            continue;
+        }
+        let position = transform.translation();
+        let in_kill_volume = kill_volumes.iter().any(|(volume, volume_transform)| {
+            let local = volume_transform
+                .affine()
+                .inverse()
+                .transform_point3(position);
+            local.abs().cmple(volume.half_extents).all()
+        });
+        if position.y < bounds.kill_height
+            || position.length() > bounds.max_distance
+            || in_kill_volume
+        {
+            out_of_bounds_events.send(OutOfBoundsEvent { body });
+        }
+    }
+}
+
+fn recover_out_of_bounds_bodies(
+    mut commands: Commands,
+    mut out_of_bounds_events: EventReader<OutOfBoundsEvent>,
+    bounds: Res<Bounds>,
+    bodies: Query<(Option<&OutOfBoundsBehavior>, Option<&SpawnPoint>)>,
+) {
+    for event in out_of_bounds_events.read() {
+        let Ok((behavior, spawn_point)) = bodies.get(event.body) else {
+            continue;
+        };
+        let behavior = behavior.copied().unwrap_or(bounds.default_behavior);
+        info!("{:?} is out of bounds, {:?}", event.body, behavior);
+
+        let Some(spawn_point) = spawn_point else {
+            commands.add(DespawnObject { body: event.body });
+            continue;
+        };
+        match behavior {
+            OutOfBoundsBehavior::Despawn => commands.add(DespawnObject { body: event.body }),
+            OutOfBoundsBehavior::RespawnAtOrigin => commands.add(ResetBody {
+                body: event.body,
+                transform: spawn_point.0,
+            }),
+            OutOfBoundsBehavior::ReturnToLastSocket => commands.add(ReturnToLastSocket {
+                body: event.body,
+                fallback: spawn_point.0,
+            }),
+        }
+    }
+}
+
+/// Despawns a body like [`DespawnBody`](crate::vr_hands::detach::DespawnBody), and frees the sockets and connectors it was attached to.
+pub struct DespawnObject {
+    pub body: Entity,
+}
+
+impl Command for DespawnObject {
+    fn apply(self, world: &mut World) {
+        if world.get_entity(self.body).is_none() {
+            return;
+        }
+        detach_object(world, self.body);
+        despawn_with_children_recursive(world, self.body);
+    }
+}
+
+/// Detaches a body like [`DespawnObject`], and moves it to a global pose at rest instead.
+pub struct ResetBody {
+    pub body: Entity,
+    pub transform: Transform,
+}
+
+impl Command for ResetBody {
+    fn apply(self, world: &mut World) {
+        if world.get_entity(self.body).is_none() {
+            return;
+        }
+        detach_object(world, self.body);
+
+        // Transform is relative to the parent, like the root of a glTF scene
+        let local = match world.get::<Parent>(self.body).map(|parent| parent.get()) {
+            Some(parent) => match world.get::<GlobalTransform>(parent) {
+                Some(parent_transform) => {
+                    GlobalTransform::from(self.transform).reparented_to(parent_transform)
+                }
+                None => self.transform,
+            },
+            None => self.transform,
+        };
+
+        let mut body = world.entity_mut(self.body);
+        if let Some(mut transform) = body.get_mut::<Transform>() {
+            *transform = local;
+        }
+        if let Some(mut position) = body.get_mut::<Position>() {
+            position.0 = self.transform.translation;
+        }
+        if let Some(mut rotation) = body.get_mut::<Rotation>() {
+            rotation.0 = self.transform.rotation;
+        }
+        if let Some(mut linear_velocity) = body.get_mut::<LinearVelocity>() {
+            linear_velocity.0 = Vec3::ZERO;
+        }
+        if let Some(mut angular_velocity) = body.get_mut::<AngularVelocity>() {
+            angular_velocity.0 = Vec3::ZERO;
+        }
+    }
+}
+
+struct ReturnToLastSocket {
+    body: Entity,
+    fallback: Transform,
+}
+
+impl Command for ReturnToLastSocket {
+    #[allow(clippy::type_complexity)]
+    fn apply(self, world: &mut World) {
+        let mut state: SystemState<(
+            Query<(Entity, &LastSocket, &GlobalTransform)>,
+            Query<(&Anchor, &GlobalTransform)>,
+            Query<&GlobalTransform>,
+            Query<(), With<RigidBody>>,
+            Query<&Parent>,
+        )> = SystemState::new(world);
+        let (last_sockets, anchors, transforms, rbs, parents) = state.get(world);
+
+        // The body pose that puts its anchorable exactly on the anchor, like the socket ghost
+        let socket = last_sockets
+            .iter()
+            .filter(|(anchorable, _, _)| body_of(*anchorable, &rbs, &parents) == Some(self.body))
+            .find_map(|(anchorable, last_socket, anchorable_transform)| {
+                let (anchor, anchor_transform) = anchors.get(last_socket.anchor).ok()?;
+                if anchor
+                    .occupant
+                    .is_some_and(|occupant| occupant != anchorable)
+                {
+                    return None;
+                }
+                let anchorable_local =
+                    anchorable_transform.reparented_to(transforms.get(self.body).ok()?);
+                let target = anchor_transform.mul_transform(Transform::from_matrix(
+                    anchorable_local.compute_matrix().inverse(),
+                ));
+                Some((anchorable, last_socket.anchor, target.compute_transform()))
+            });
+
+        let Some((anchorable, anchor, target)) = socket else {
+            ResetBody {
+                body: self.body,
+                transform: self.fallback,
+            }
+            .apply(world);
+            return;
+        };
+
+        ResetBody {
+            body: self.body,
+            transform: target,
+        }
+        .apply(world);
+        // The anchor may have moved since, so the old joint's frames could be stale
+        if socket_into(world, anchorable, anchor) {
+            debug!("Returning {:?} to {:?}", anchorable, anchor);
+        }
+    }
+}
+
+/// Lets go of a body like [`detach_body`], first freeing the anchors and connectors on either side
+/// of its sockets and connections.
+#[allow(clippy::type_complexity)]
+pub fn detach_object(world: &mut World, body: Entity) {
+    let parts = parts_of(world, body);
+    let mut state: SystemState<(
+        Commands,
+        Query<&Connection>,
+        Query<(Entity, &Socketed)>,
+        Query<&mut Anchor>,
+        Query<&mut Connector>,
+    )> = SystemState::new(world);
+    let (mut commands, connections, socketed, mut anchors, mut connectors) = state.get_mut(world);
+
+    // The connection joints themselves go with the rest of the body's joints
+    for connection in connections.iter() {
+        let connector_parts = [connection.connector1, connection.connector2];
+        if !connector_parts
+            .iter()
+            .any(|connector| parts.contains(connector))
+        {
+            continue;
+        }
+        for connector in connector_parts {
+            if let Ok(mut connector) = connectors.get_mut(connector) {
+                connector.connected_to = None;
+            }
+        }
+    }
+
+    // Sockets on either side: anchorables in the body, or anchors in the body holding others
+    for (anchorable, socketed) in socketed.iter() {
+        if !parts.contains(&anchorable) && !parts.contains(&socketed.anchor) {
+            continue;
+        }
+        commands.entity(anchorable).remove::<Socketed>();
+        if let Ok(mut anchor) = anchors.get_mut(socketed.anchor) {
+            anchor.occupant = None;
+        }
+    }
+
+    state.apply(world);
+    detach_body(world, body);
+}
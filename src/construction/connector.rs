@@ -0,0 +1,242 @@
+use std::{collections::VecDeque, f32::consts::PI};
+
+use bevy::{
+    ecs::{
+        entity::{EntityMapper, MapEntities},
+        reflect::ReflectMapEntities,
+    },
+    prelude::*,
+};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    body_of,
+    vr_hands::{
+        fixed_joint_2::FixedJoint2,
+        grabber::{held_bodies, Grabber},
+    },
+};
+
+pub struct ConnectorPlugin;
+
+impl Plugin for ConnectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Connector>()
+            .register_type::<Connection>()
+            .add_systems(
+                Update,
+                (
+                    break_overloaded_connections,
+                    clear_separated_connectors,
+                    connect_held_parts,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// A point on a part that snaps to other parts' connectors when brought close while held.
+///
+/// Connectors mate facing each other: once connected, the second connector is rotated half a
+/// turn around the first connector's Y axis.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct Connector {
+    /// How close another connector has to be to snap to this one.
+    pub snap_radius: f32,
+    /// The force a connection made with this connector can take before the parts come apart.
+    pub break_force: f32,
+    /// The connector this one is currently connected to.
+    pub connected_to: Option<Entity>,
+}
+
+impl Default for Connector {
+    fn default() -> Self {
+        Self {
+            snap_radius: 0.03,
+            break_force: 50.0,
+            connected_to: None,
+        }
+    }
+}
+
+/// Marks a joint created by snapping two connectors together.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, MapEntities)]
+pub struct Connection {
+    pub connector1: Entity,
+    pub connector2: Entity,
+    pub break_force: f32,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self {
+            connector1: Entity::PLACEHOLDER,
+            connector2: Entity::PLACEHOLDER,
+            break_force: Connector::default().break_force,
+        }
+    }
+}
+
+impl MapEntities for Connection {
+    fn map_entities(&mut self, entity_mapper: &mut EntityMapper) {
+        self.connector1 = entity_mapper.get_or_reserve(self.connector1);
+        self.connector2 = entity_mapper.get_or_reserve(self.connector2);
+    }
+}
+
+/// Added to connectors that were just pulled apart, so they don't snap straight back together.
+#[derive(Component)]
+struct Separating;
+
+/// All parts connected to `part` through connections, including `part` itself.
+pub fn assembly_of(
+    part: Entity,
+    connections: &Query<&FixedJoint2, With<Connection>>,
+) -> Vec<Entity> {
+    let mut assembly = vec![part];
+    let mut queue = VecDeque::from([part]);
+    while let Some(current) = queue.pop_front() {
+        for joint in connections.iter() {
+            let other = if joint.entity1 == current {
+                joint.entity2
+            } else if joint.entity2 == current {
+                joint.entity1
+            } else {
+                continue;
+            };
+            if !assembly.contains(&other) {
+                assembly.push(other);
+                queue.push_back(other);
+            }
+        }
+    }
+    assembly
+}
+
+fn break_overloaded_connections(
+    mut commands: Commands,
+    joints: Query<(Entity, &FixedJoint2, &Connection)>,
+    mut connectors: Query<&mut Connector>,
+) {
+    for (joint_entity, joint, connection) in joints.iter() {
+        if joint.force.length() < connection.break_force {
+            continue;
+        }
+
+        debug!("Breaking connection {:?}", joint_entity);
+        commands.entity(joint_entity).despawn_recursive();
+        for connector_entity in [connection.connector1, connection.connector2] {
+            if let Ok(mut connector) = connectors.get_mut(connector_entity) {
+                connector.connected_to = None;
+                commands.entity(connector_entity).insert(Separating);
+            }
+        }
+    }
+}
+
+fn clear_separated_connectors(
+    mut commands: Commands,
+    separating: Query<(Entity, &Connector, &GlobalTransform), With<Separating>>,
+    connectors: Query<(Entity, &GlobalTransform), With<Connector>>,
+    rbs: Query<(), With<RigidBody>>,
+    parents: Query<&Parent>,
+) {
+    for (entity, connector, transform) in separating.iter() {
+        let body = body_of(entity, &rbs, &parents);
+        let still_close = connectors.iter().any(|(other, other_transform)| {
+            other != entity
+                && body_of(other, &rbs, &parents) != body
+                && other_transform
+                    .translation()
+                    .distance(transform.translation())
+                    <= connector.snap_radius * 2.0
+        });
+        if !still_close {
+            commands.entity(entity).remove::<Separating>();
+        }
+    }
+}
+
+fn connect_held_parts(
+    mut commands: Commands,
+    grabbers: Query<&Grabber>,
+    mut connectors: Query<(Entity, &mut Connector, &GlobalTransform), Without<Separating>>,
+    connections: Query<&FixedJoint2, With<Connection>>,
+    transforms: Query<&GlobalTransform>,
+    rbs: Query<(), With<RigidBody>>,
+    parents: Query<&Parent>,
+) {
+    let held = held_bodies(&grabbers);
+    let free_connectors: Vec<(Entity, Entity, GlobalTransform, &Connector)> = connectors
+        .iter()
+        .filter(|(_, connector, _)| connector.connected_to.is_none())
+        .filter_map(|(entity, connector, transform)| {
+            body_of(entity, &rbs, &parents).map(|body| (entity, body, *transform, connector))
+        })
+        .collect();
+
+    let mut new_connections = vec![];
+    for (held_connector, held_body, held_transform, held_config) in free_connectors
+        .iter()
+        .filter(|(_, body, _, _)| held.contains(body))
+    {
+        let snapped = |connector: &Entity| {
+            new_connections
+                .iter()
+                .any(|(c1, c2)| c1 == connector || c2 == connector)
+        };
+        if snapped(held_connector) {
+            continue;
+        }
+
+        // Don't close loops within an assembly, the parts are already held in place
+        let assembly = assembly_of(*held_body, &connections);
+        let Some((other_connector, other_body, other_transform, other_config, _)) = free_connectors
+            .iter()
+            .filter(|(connector, body, _, _)| !assembly.contains(body) && !snapped(connector))
+            .map(|(connector, body, transform, config)| {
+                let distance = transform
+                    .translation()
+                    .distance(held_transform.translation());
+                (connector, body, transform, config, distance)
+            })
+            .filter(|(_, _, _, config, distance)| {
+                *distance <= held_config.snap_radius.max(config.snap_radius)
+            })
+            .min_by(|(_, _, _, _, d1), (_, _, _, _, d2)| d1.partial_cmp(d2).unwrap())
+        else {
+            continue;
+        };
+
+        // Express both connector frames relative to the bodies the joint connects
+        let other_local = other_transform.reparented_to(transforms.get(*other_body).unwrap());
+        let held_local = held_transform.reparented_to(transforms.get(*held_body).unwrap());
+        // other_body_rotation * other_local.rotation * flip = held_body_rotation * held_local.rotation
+        let flip = Quat::from_rotation_y(PI);
+        let rotation_offset = other_local.rotation * flip * held_local.rotation.inverse();
+        let break_force = held_config.break_force.min(other_config.break_force);
+
+        commands.spawn((
+            FixedJoint2::new(*other_body, *held_body)
+                .with_local_anchor_1(other_local.translation)
+                .with_local_anchor_2(held_local.translation)
+                .with_rotation_offset(rotation_offset.into()),
+            Connection {
+                connector1: *other_connector,
+                connector2: *held_connector,
+                break_force,
+            },
+            Name::new("Connection Joint"),
+        ));
+
+        debug!("Connecting {:?} to {:?}", held_body, other_body);
+        new_connections.push((*other_connector, *held_connector));
+    }
+
+    for (connector1, connector2) in new_connections {
+        connectors.get_mut(connector1).unwrap().1.connected_to = Some(connector2);
+        connectors.get_mut(connector2).unwrap().1.connected_to = Some(connector1);
+    }
+}
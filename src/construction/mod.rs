@@ -0,0 +1,13 @@
+use bevy::prelude::*;
+
+pub mod connector;
+pub mod weld;
+
+pub struct ConstructionPlugin;
+
+impl Plugin for ConstructionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(connector::ConnectorPlugin)
+            .add_plugins(weld::WeldPlugin);
+    }
+}
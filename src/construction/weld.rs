@@ -0,0 +1,486 @@
+//! Welding turns an assembly of parts held together by connection joints into a single compound
+//! rigid body, with the other parts' colliders attached to one root part. Long joint chains are
+//! soft in practice, a compound body is perfectly rigid and cheaper to simulate.
+
+use bevy::{
+    ecs::{
+        entity::{EntityMapper, MapEntities},
+        reflect::ReflectMapEntities,
+    },
+    prelude::*,
+};
+use bevy_xpbd_3d::prelude::*;
+
+use super::connector::{assembly_of, Connection};
+use crate::vr_hands::{
+    fixed_joint_2::FixedJoint2,
+    generic_joint::GenericJoint,
+    grabber::{Grabber, GrabberState},
+};
+
+pub struct WeldPlugin;
+
+impl Plugin for WeldPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Welded>()
+            .register_type::<(FixedJoint2, Connection)>()
+            .register_type::<Vec<(FixedJoint2, Connection)>>()
+            .register_type::<MovedJoint>()
+            .register_type::<Vec<MovedJoint>>()
+            .add_event::<WeldEvent>()
+            .add_event::<UnweldEvent>()
+            .add_systems(Update, (weld_assemblies, unweld_assemblies).chain());
+    }
+}
+
+/// Weld the assembly containing `part` into a single compound body.
+#[derive(Event)]
+pub struct WeldEvent {
+    pub part: Entity,
+}
+
+/// Split the welded compound body `root` back into individual parts and connection joints.
+#[derive(Event)]
+pub struct UnweldEvent {
+    pub root: Entity,
+}
+
+/// The root part of a welded assembly, along with what was removed to weld it.
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component, MapEntities)]
+pub struct Welded {
+    pub parts: Vec<Entity>,
+    pub joints: Vec<(FixedJoint2, Connection)>,
+    /// Joints to things outside the assembly that were moved onto the root, in the order they were
+    /// moved. Joints attached to the root while welded stay on it when unwelding.
+    pub moved_joints: Vec<MovedJoint>,
+}
+
+impl MapEntities for Welded {
+    fn map_entities(&mut self, entity_mapper: &mut EntityMapper) {
+        for part in self.parts.iter_mut() {
+            *part = entity_mapper.get_or_reserve(*part);
+        }
+        for (joint, connection) in self.joints.iter_mut() {
+            joint.map_entities(entity_mapper);
+            connection.map_entities(entity_mapper);
+        }
+        for moved in self.moved_joints.iter_mut() {
+            moved.joint = entity_mapper.get_or_reserve(moved.joint);
+            moved.part = entity_mapper.get_or_reserve(moved.part);
+        }
+    }
+}
+
+/// One end of a joint, like a grab or a socket, that was moved from `part` to the root of a weld.
+#[derive(Reflect, Debug, Clone, Copy)]
+pub struct MovedJoint {
+    pub joint: Entity,
+    pub part: Entity,
+    /// Whether `part` was the joint's second body rather than its first.
+    pub second: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn weld_assemblies(
+    mut commands: Commands,
+    mut weld_events: EventReader<WeldEvent>,
+    connections: Query<&FixedJoint2, With<Connection>>,
+    connection_joints: Query<(Entity, &FixedJoint2, &Connection)>,
+    mut other_joints: Query<(Entity, &mut FixedJoint2), Without<Connection>>,
+    mut generic_joints: Query<(Entity, &mut GenericJoint)>,
+    welded: Query<&Welded>,
+    mut grabbers: Query<&mut Grabber>,
+    transforms: Query<&GlobalTransform>,
+    colliders: Query<&Collider>,
+    children: Query<&Children>,
+) {
+    for event in weld_events.read() {
+        let assembly = assembly_of(event.part, &connections);
+        if assembly.len() < 2 {
+            continue;
+        }
+
+        // Grow an existing compound body if there is one, so welds can be built up incrementally
+        let root = assembly
+            .iter()
+            .copied()
+            .find(|part| welded.get(*part).is_ok())
+            .unwrap_or(event.part);
+        let root_transform = transforms.get(root).unwrap();
+        let mut weld = welded.get(root).cloned().unwrap_or_default();
+
+        for (joint_entity, joint, connection) in connection_joints.iter() {
+            if assembly.contains(&joint.entity1) && assembly.contains(&joint.entity2) {
+                commands.entity(joint_entity).despawn_recursive();
+                weld.joints.push((*joint, *connection));
+            }
+        }
+
+        for part in assembly.iter().copied().filter(|part| *part != root) {
+            let part_local = transforms.get(part).unwrap().reparented_to(root_transform);
+
+            // Joints to things outside the assembly, like grabs and sockets, now hold the root
+            move_joint_ends(other_joints.iter_mut(), part, root, &part_local, &mut weld);
+            move_joint_ends(
+                generic_joints.iter_mut(),
+                part,
+                root,
+                &part_local,
+                &mut weld,
+            );
+            for mut grabber in grabbers.iter_mut() {
+                if let GrabberState::Grabbed(entity, joint) = grabber.state {
+                    if entity == part {
+                        grabber.state = GrabberState::Grabbed(root, joint);
+                    }
+                }
+            }
+
+            // Parts that were welded before come along with their own compound body
+            if let Ok(part_weld) = welded.get(part) {
+                weld.parts.extend(part_weld.parts.iter().copied());
+                weld.joints.extend(part_weld.joints.iter().copied());
+                weld.moved_joints
+                    .extend(part_weld.moved_joints.iter().copied());
+                commands.entity(part).remove::<Welded>();
+            }
+
+            // Without a rigid body of its own, the part's colliders become part of the root's
+            commands
+                .entity(part)
+                .remove::<(RigidBody, LinearVelocity, AngularVelocity)>()
+                .remove::<MassPropertiesBundle>()
+                .set_parent_in_place(root);
+            readd_colliders(&mut commands, part, &colliders, &children);
+            weld.parts.push(part);
+        }
+
+        debug!("Welded {} parts into {:?}", weld.parts.len() + 1, root);
+        commands.entity(root).insert(weld);
+    }
+}
+
+/// Removes and inserts the colliders in `part` again. Physics only moves a collider's mass from one
+/// body to another when it's removed and added, not when the body it belongs to changes.
+fn readd_colliders(
+    commands: &mut Commands,
+    part: Entity,
+    colliders: &Query<&Collider>,
+    children: &Query<&Children>,
+) {
+    for entity in std::iter::once(part).chain(children.iter_descendants(part)) {
+        if let Ok(collider) = colliders.get(entity) {
+            commands
+                .entity(entity)
+                .remove::<Collider>()
+                .insert(collider.clone());
+        }
+    }
+}
+
+/// A joint whose ends can be moved from one body to another.
+trait JointEnds {
+    fn end(&mut self, second: bool) -> &mut Entity;
+
+    /// Re-expresses where an end attaches in the frame of the body it moves to, given the body it
+    /// moves from relative to that one.
+    fn move_attachment(&mut self, second: bool, from_local: &Transform);
+}
+
+impl JointEnds for FixedJoint2 {
+    fn end(&mut self, second: bool) -> &mut Entity {
+        match second {
+            false => &mut self.entity1,
+            true => &mut self.entity2,
+        }
+    }
+
+    fn move_attachment(&mut self, second: bool, from_local: &Transform) {
+        if second {
+            self.local_anchor2 = from_local.transform_point(self.local_anchor2);
+            self.rotation_offset = Rotation(self.rotation_offset.0 * from_local.rotation.inverse());
+        } else {
+            self.local_anchor1 = from_local.transform_point(self.local_anchor1);
+            self.rotation_offset = Rotation(from_local.rotation * self.rotation_offset.0);
+        }
+    }
+}
+
+impl JointEnds for GenericJoint {
+    fn end(&mut self, second: bool) -> &mut Entity {
+        match second {
+            false => &mut self.entity1,
+            true => &mut self.entity2,
+        }
+    }
+
+    // Each end has a frame of its own, so only the moved one changes
+    fn move_attachment(&mut self, second: bool, from_local: &Transform) {
+        if second {
+            self.local_anchor2 = from_local.transform_point(self.local_anchor2);
+            self.local_frame2 = from_local.rotation * self.local_frame2;
+        } else {
+            self.local_anchor1 = from_local.transform_point(self.local_anchor1);
+            self.local_frame1 = from_local.rotation * self.local_frame1;
+        }
+    }
+}
+
+/// Moves one end of `joint` from `from` to `to`, keeping it attached at the same place. `from_local`
+/// is `from` relative to `to`. Returns whether that end was attached to `from`.
+fn move_joint_end(
+    joint: &mut impl JointEnds,
+    second: bool,
+    from: Entity,
+    to: Entity,
+    from_local: &Transform,
+) -> bool {
+    if *joint.end(second) != from {
+        return false;
+    }
+    *joint.end(second) = to;
+    joint.move_attachment(second, from_local);
+    true
+}
+
+/// Moves the ends of `joints` attached to `part` onto the weld's `root`, and records the moves.
+fn move_joint_ends<'a, J: JointEnds + Component>(
+    joints: impl Iterator<Item = (Entity, Mut<'a, J>)>,
+    part: Entity,
+    root: Entity,
+    part_local: &Transform,
+    weld: &mut Welded,
+) {
+    for (joint_entity, mut joint) in joints {
+        for second in [false, true] {
+            if move_joint_end(&mut *joint, second, part, root, part_local) {
+                weld.moved_joints.push(MovedJoint {
+                    joint: joint_entity,
+                    part,
+                    second,
+                });
+            }
+        }
+    }
+}
+
+/// Moves a joint end recorded in `moved` back from wherever it is now onto its part. Returns
+/// whether it could.
+fn move_joint_end_back(
+    joint: &mut impl JointEnds,
+    moved: &MovedJoint,
+    transforms: &Query<&GlobalTransform>,
+) -> bool {
+    let holder = *joint.end(moved.second);
+    let (Ok(holder_transform), Ok(part_transform)) =
+        (transforms.get(holder), transforms.get(moved.part))
+    else {
+        return false;
+    };
+    let holder_local = holder_transform.reparented_to(part_transform);
+    move_joint_end(joint, moved.second, holder, moved.part, &holder_local)
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn unweld_assemblies(
+    mut commands: Commands,
+    mut unweld_events: EventReader<UnweldEvent>,
+    welded: Query<(&Welded, &GlobalTransform, &LinearVelocity, &AngularVelocity)>,
+    mut other_joints: Query<&mut FixedJoint2, Without<Connection>>,
+    mut generic_joints: Query<&mut GenericJoint>,
+    mut grabbers: Query<&mut Grabber>,
+    transforms: Query<&GlobalTransform>,
+    colliders: Query<&Collider>,
+    children: Query<&Children>,
+) {
+    for event in unweld_events.read() {
+        let Ok((weld, root_transform, linear_velocity, angular_velocity)) = welded.get(event.root)
+        else {
+            continue;
+        };
+
+        // Undo the moves last to first, so joints moved through an earlier weld's root end up back
+        // on the part they started on
+        for moved in weld.moved_joints.iter().rev() {
+            let moved_back = if let Ok(mut joint) = other_joints.get_mut(moved.joint) {
+                move_joint_end_back(&mut *joint, moved, &transforms)
+            } else if let Ok(mut joint) = generic_joints.get_mut(moved.joint) {
+                move_joint_end_back(&mut *joint, moved, &transforms)
+            } else {
+                false
+            };
+            if !moved_back {
+                continue;
+            }
+            for mut grabber in grabbers.iter_mut() {
+                if let GrabberState::Grabbed(_, joint) = grabber.state {
+                    if joint == moved.joint {
+                        grabber.state = GrabberState::Grabbed(moved.part, joint);
+                    }
+                }
+            }
+        }
+
+        for part in weld.parts.iter().copied() {
+            // Parts keep moving with the velocity they had as part of the compound body
+            let offset = transforms.get(part).unwrap().translation() - root_transform.translation();
+            commands.entity(part).remove_parent_in_place().insert((
+                RigidBody::Dynamic,
+                LinearVelocity(linear_velocity.0 + angular_velocity.0.cross(offset)),
+                AngularVelocity(angular_velocity.0),
+            ));
+            readd_colliders(&mut commands, part, &colliders, &children);
+        }
+
+        for (joint, connection) in weld.joints.iter() {
+            commands.spawn((*joint, *connection, Name::new("Connection Joint")));
+        }
+
+        debug!("Unwelded {:?}", event.root);
+        commands.entity(event.root).remove::<Welded>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::vr_hands::{fixed_joint_2::FixedJoint2Plugin, generic_joint::GenericJointPlugin};
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            HierarchyPlugin,
+            AssetPlugin::default(),
+            bevy::scene::ScenePlugin,
+        ))
+        .init_asset::<Mesh>()
+        .add_plugins((
+            PhysicsPlugins::default(),
+            FixedJoint2Plugin,
+            GenericJointPlugin,
+            WeldPlugin,
+        ))
+        .insert_resource(Gravity(Vec3::ZERO))
+        // A physics step every update
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            1.0 / 60.0,
+        )));
+        app
+    }
+
+    fn part(world: &mut World, x: f32, rotation: Quat) -> Entity {
+        world
+            .spawn((
+                RigidBody::Dynamic,
+                Collider::cuboid(0.5, 0.5, 0.5),
+                TransformBundle::from_transform(
+                    Transform::from_xyz(x, 0.0, 0.0).with_rotation(rotation),
+                ),
+            ))
+            .id()
+    }
+
+    fn mass(world: &World, entity: Entity) -> f32 {
+        world.get::<Mass>(entity).unwrap().0
+    }
+
+    #[test]
+    fn unwelding_restores_joints_and_masses() {
+        let mut app = app();
+        let world = &mut app.world;
+        // A row of parts a meter apart, with every joint already satisfied so nothing moves
+        let turned = Quat::from_rotation_y(0.5);
+        let root = part(world, 0.0, Quat::IDENTITY);
+        let other = part(world, 1.0, turned);
+        let hand = part(world, 2.0, Quat::IDENTITY);
+        world.spawn((
+            FixedJoint2::new(root, other)
+                .with_local_anchor_1(Vec3::new(0.5, 0.0, 0.0))
+                .with_local_anchor_2(turned.inverse() * Vec3::new(-0.5, 0.0, 0.0))
+                .with_rotation_offset(Rotation(turned)),
+            Connection::default(),
+        ));
+        let grab = FixedJoint2::new(hand, other)
+            .with_local_anchor_1(Vec3::new(-0.5, 0.0, 0.0))
+            .with_local_anchor_2(turned.inverse() * Vec3::new(0.5, 0.0, 0.0))
+            .with_rotation_offset(Rotation(turned));
+        let grab_entity = world.spawn(grab).id();
+        app.update();
+        let masses = [mass(&app.world, root), mass(&app.world, other)];
+
+        app.world.send_event(WeldEvent { part: root });
+        app.update();
+        app.update();
+        let welded = *app.world.get::<FixedJoint2>(grab_entity).unwrap();
+        assert_eq!(welded.entity2, root);
+        assert!(welded
+            .local_anchor2
+            .abs_diff_eq(Vec3::new(1.5, 0.0, 0.0), 1e-3));
+        assert!((mass(&app.world, root) - masses[0] - masses[1]).abs() < 1e-3);
+
+        app.world.send_event(UnweldEvent { root });
+        app.update();
+        app.update();
+        let unwelded = *app.world.get::<FixedJoint2>(grab_entity).unwrap();
+        assert_eq!(unwelded.entity1, hand);
+        assert_eq!(unwelded.entity2, other);
+        assert!(unwelded.local_anchor1.abs_diff_eq(grab.local_anchor1, 1e-3));
+        assert!(unwelded.local_anchor2.abs_diff_eq(grab.local_anchor2, 1e-3));
+        assert!(unwelded
+            .rotation_offset
+            .0
+            .abs_diff_eq(grab.rotation_offset.0, 1e-3));
+        assert!((mass(&app.world, root) - masses[0]).abs() < 1e-3);
+        assert!((mass(&app.world, other) - masses[1]).abs() < 1e-3);
+    }
+
+    #[test]
+    fn welding_moves_generic_joints_too() {
+        let mut app = app();
+        let world = &mut app.world;
+        let turned = Quat::from_rotation_y(0.5);
+        let root = part(world, 0.0, Quat::IDENTITY);
+        let other = part(world, 1.0, turned);
+        let hand = part(world, 2.0, Quat::IDENTITY);
+        world.spawn((
+            FixedJoint2::new(root, other)
+                .with_local_anchor_1(Vec3::new(0.5, 0.0, 0.0))
+                .with_local_anchor_2(turned.inverse() * Vec3::new(-0.5, 0.0, 0.0))
+                .with_rotation_offset(Rotation(turned)),
+            Connection::default(),
+        ));
+        let grab = GenericJoint::new(hand, other)
+            .with_local_anchor_1(Vec3::new(-0.5, 0.0, 0.0))
+            .with_local_anchor_2(turned.inverse() * Vec3::new(0.5, 0.0, 0.0))
+            .with_local_frame_2(turned.inverse());
+        let grab_entity = world.spawn(grab).id();
+        app.update();
+
+        app.world.send_event(WeldEvent { part: root });
+        app.update();
+        app.update();
+        let welded = *app.world.get::<GenericJoint>(grab_entity).unwrap();
+        assert_eq!(welded.entity2, root);
+        assert!(welded
+            .local_anchor2
+            .abs_diff_eq(Vec3::new(1.5, 0.0, 0.0), 1e-3));
+        assert!(welded.local_frame2.abs_diff_eq(Quat::IDENTITY, 1e-3));
+
+        app.world.send_event(UnweldEvent { root });
+        app.update();
+        app.update();
+        let unwelded = *app.world.get::<GenericJoint>(grab_entity).unwrap();
+        assert_eq!(unwelded.entity1, hand);
+        assert_eq!(unwelded.entity2, other);
+        assert!(unwelded.local_anchor2.abs_diff_eq(grab.local_anchor2, 1e-3));
+        assert!(unwelded.local_frame1.abs_diff_eq(grab.local_frame1, 1e-3));
+        assert!(unwelded.local_frame2.abs_diff_eq(grab.local_frame2, 1e-3));
+    }
+}
@@ -1,13 +1,15 @@
 use bevy::prelude::*;
 
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
-use bevy_oxr::input::XrInput;
-use bevy_oxr::resources::{XrFrameState, XrInstance, XrSession};
 use bevy_oxr::xr_input::debug_gizmos::OpenXrDebugRenderer;
-use bevy_oxr::xr_input::oculus_touch::OculusController;
 use bevy_oxr::xr_input::prototype_locomotion::{proto_locomotion, PrototypeLocomotionConfig};
+use bevy_oxr::xr_input::Hand;
 use bevy_xpbd_3d::plugins::setup::{Physics, PhysicsTime};
 
+use crate::input::InputState;
+use crate::vr_hands::grabber::{Grabber, GrabberState};
+use crate::vr_hands::joint_gizmos::{JointGizmos, JointGizmosPlugin};
+
 pub struct DebugPlugin;
 
 impl Plugin for DebugPlugin {
@@ -15,37 +17,43 @@ impl Plugin for DebugPlugin {
         app.add_plugins(OpenXrDebugRenderer)
             .add_plugins(LogDiagnosticsPlugin::default())
             .add_plugins(FrameTimeDiagnosticsPlugin)
-            .add_systems(Update, (proto_locomotion, toggle_physics))
+            .add_plugins(JointGizmosPlugin)
+            .add_systems(
+                Update,
+                (proto_locomotion, toggle_physics, toggle_joint_gizmos),
+            )
             .insert_resource(PrototypeLocomotionConfig::default());
     }
 }
 
-// Toggle physics when the A or X buttons are pressed on each touch controller.
+// Toggle physics when the A or X button is pressed on an empty hand. A hand holding something
+// leaves the button to the held object, which may be a usable bound to it.
 fn toggle_physics(
-    oculus_controller: Res<OculusController>,
-    frame_state: Res<XrFrameState>,
-    xr_input: Res<XrInput>,
-    instance: Res<XrInstance>,
-    session: Res<XrSession>,
+    input_state: Res<InputState>,
+    grabbers: Query<&Grabber>,
     mut physics_time: ResMut<Time<Physics>>,
-    mut currently_pressed: Local<bool>,
 ) {
-    // magic code to get the controller
-    let frame_state = *frame_state.lock().unwrap();
-    let controller = oculus_controller.get_ref(&instance, &session, &frame_state, &xr_input);
-
-    if controller.a_button() || controller.x_button() {
-        if *currently_pressed {
-            return;
-        }
-        *currently_pressed = true;
-        // Toggle physics
-        if physics_time.is_paused() {
-            physics_time.unpause();
-        } else {
-            physics_time.pause();
-        }
+    let pressed = grabbers.iter().any(|grabber| {
+        let just_pressed = match Hand::from(grabber.hand) {
+            Hand::Left => input_state.x_button.just_pressed,
+            Hand::Right => input_state.a_button.just_pressed,
+        };
+        just_pressed && matches!(grabber.state, GrabberState::Idle)
+    });
+    if !pressed {
+        return;
+    }
+    if physics_time.is_paused() {
+        physics_time.unpause();
     } else {
-        *currently_pressed = false;
+        physics_time.pause();
+    }
+}
+
+// Toggle joint gizmos when the menu button is pressed.
+fn toggle_joint_gizmos(input_state: Res<InputState>, mut joint_gizmos: ResMut<JointGizmos>) {
+    if input_state.menu_button.just_pressed {
+        joint_gizmos.enabled = !joint_gizmos.enabled;
+        info!("Joint gizmos enabled: {}", joint_gizmos.enabled);
     }
 }
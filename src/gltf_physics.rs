@@ -0,0 +1,204 @@
+//! Physics authored in Blender. These components are read from glTF extras by
+//! `ComponentsFromGltfPlugin` and turned into rigid bodies and colliders once the scene spawns.
+
+use bevy::prelude::*;
+use bevy_scene_hook::SceneHooked;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{vr_hands::grabber::Grabbable, Layer};
+
+pub struct GltfPhysicsPlugin;
+
+impl Plugin for GltfPhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PhysicsBody>()
+            .register_type::<BodyKind>()
+            .register_type::<GltfCollider>()
+            .register_type::<ColliderShape>()
+            .add_systems(
+                Update,
+                (
+                    apply_fallback_physics,
+                    insert_physics_bodies,
+                    generate_gltf_colliders,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Makes the node a rigid body. Colliders come from [`GltfCollider`] on the node or its children.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component)]
+pub struct PhysicsBody {
+    pub kind: BodyKind,
+    /// Density of the body's colliders, in kg/m^3.
+    pub density: f32,
+}
+
+impl Default for PhysicsBody {
+    fn default() -> Self {
+        Self {
+            kind: BodyKind::Dynamic,
+            density: 1000.0,
+        }
+    }
+}
+
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BodyKind {
+    #[default]
+    Dynamic,
+    Static,
+    Kinematic,
+}
+
+/// Gives the node a collider, either a primitive or one generated from the node's meshes.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default)]
+#[reflect(Component)]
+pub struct GltfCollider {
+    pub shape: ColliderShape,
+}
+
+#[derive(Reflect, Debug, Clone, Copy, Default)]
+pub enum ColliderShape {
+    /// The convex hull of the node's meshes. Cheap, and fine for most dynamic objects.
+    #[default]
+    ConvexHull,
+    /// The node's meshes as they are. Exact, but only suited to static geometry.
+    Trimesh,
+    Ball {
+        radius: f32,
+    },
+    /// A box with the given full extents.
+    Cuboid {
+        size: Vec3,
+    },
+}
+
+/// Physics for a scene root, for libraries exported without extras. Applied once the scene has
+/// spawned, unless the scene has a [`PhysicsBody`] of its own.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct FallbackPhysics {
+    pub body: PhysicsBody,
+    pub collider: ColliderShape,
+    /// Where the collider is relative to the scene root.
+    pub collider_offset: Vec3,
+    pub grabbable: bool,
+}
+
+/// Added to [`GltfCollider`] nodes once their colliders exist.
+#[derive(Component)]
+struct ColliderGenerated;
+
+fn apply_fallback_physics(
+    mut commands: Commands,
+    roots: Query<(Entity, &FallbackPhysics), Added<SceneHooked>>,
+    bodies: Query<(), With<PhysicsBody>>,
+    children: Query<&Children>,
+) {
+    for (root, fallback) in roots.iter() {
+        commands.entity(root).remove::<FallbackPhysics>();
+        if children
+            .iter_descendants(root)
+            .any(|entity| bodies.contains(entity))
+        {
+            continue;
+        }
+
+        commands
+            .entity(root)
+            .insert(fallback.body)
+            .with_children(|parent| {
+                parent.spawn((
+                    SpatialBundle::from_transform(Transform::from_translation(
+                        fallback.collider_offset,
+                    )),
+                    GltfCollider {
+                        shape: fallback.collider,
+                    },
+                    Name::new("Collider"),
+                ));
+            });
+        if fallback.grabbable {
+            commands.entity(root).insert(Grabbable::default());
+        }
+    }
+}
+
+fn insert_physics_bodies(
+    mut commands: Commands,
+    bodies: Query<(Entity, &PhysicsBody), Added<PhysicsBody>>,
+) {
+    for (entity, body) in bodies.iter() {
+        let rigid_body = match body.kind {
+            BodyKind::Dynamic => RigidBody::Dynamic,
+            BodyKind::Static => RigidBody::Static,
+            BodyKind::Kinematic => RigidBody::Kinematic,
+        };
+        commands.entity(entity).insert(rigid_body);
+    }
+}
+
+fn generate_gltf_colliders(
+    mut commands: Commands,
+    colliders: Query<(Entity, &GltfCollider), Without<ColliderGenerated>>,
+    mesh_handles: Query<&Handle<Mesh>>,
+    meshes: Res<Assets<Mesh>>,
+    bodies: Query<(&PhysicsBody, Has<Grabbable>)>,
+    children: Query<&Children>,
+    parents: Query<&Parent>,
+) {
+    for (entity, collider) in colliders.iter() {
+        let (density, grabbable) = std::iter::once(entity)
+            .chain(parents.iter_ancestors(entity))
+            .find_map(|e| bodies.get(e).ok())
+            .map(|(body, grabbable)| (body.density, grabbable))
+            .unwrap_or((PhysicsBody::default().density, false));
+        let layers = match grabbable {
+            true => CollisionLayers::new([Layer::Grabbable, Layer::Default], [Layer::Default]),
+            false => CollisionLayers::new([Layer::Default], [Layer::Default]),
+        };
+
+        let generated = match collider.shape {
+            ColliderShape::Ball { radius } => vec![(entity, Collider::ball(radius))],
+            ColliderShape::Cuboid { size } => {
+                vec![(entity, Collider::cuboid(size.x, size.y, size.z))]
+            }
+            ColliderShape::ConvexHull | ColliderShape::Trimesh => {
+                let mut generated = vec![];
+                let mut loaded = true;
+                for part in std::iter::once(entity).chain(children.iter_descendants(entity)) {
+                    let Ok(handle) = mesh_handles.get(part) else {
+                        continue;
+                    };
+                    let Some(mesh) = meshes.get(handle) else {
+                        loaded = false;
+                        break;
+                    };
+                    let part_collider = match collider.shape {
+                        ColliderShape::Trimesh => Collider::trimesh_from_mesh(mesh),
+                        _ => Collider::convex_hull_from_mesh(mesh),
+                    };
+                    match part_collider {
+                        Some(part_collider) => generated.push((part, part_collider)),
+                        None => warn!("Could not generate a collider for {:?}", part),
+                    }
+                }
+                // Try again once all the meshes are loaded
+                if !loaded {
+                    continue;
+                }
+                generated
+            }
+        };
+
+        // Each mesh gets its own collider, so they follow the mesh transforms within the body
+        for (part, part_collider) in generated {
+            commands
+                .entity(part)
+                .insert((part_collider, ColliderDensity(density), layers));
+        }
+        commands.entity(entity).insert(ColliderGenerated);
+    }
+}
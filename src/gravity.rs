@@ -0,0 +1,160 @@
+//! Gravity that changes from place to place and body to body, on top of the global [`Gravity`].
+//!
+//! Physics only knows the global gravity, so bodies under different gravity get a force making
+//! up the difference, added on top of whatever other forces they have. The global gravity applies everywhere outside the zones,
+//! so in a low gravity scene, an area with normal gravity needs a
+//! [`ZoneGravity::Directional`] zone of its own. Scene descriptions can also set the global gravity.
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::{prelude::*, PhysicsSchedule, PhysicsStepSet};
+
+use crate::vr_hands::velocity_tracking::VelocityTracked;
+
+pub struct GravityPlugin;
+
+impl Plugin for GravityPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<GravityZone>()
+            .register_type::<ZoneShape>()
+            .register_type::<ZoneGravity>()
+            .register_type::<BodyGravityScale>()
+            .register_type::<LocalGravityForce>();
+
+        app.get_schedule_mut(PhysicsSchedule)
+            .expect("add PhysicsSchedule first")
+            .add_systems(
+                apply_local_gravity
+                    .after(PhysicsStepSet::BroadPhase)
+                    .before(PhysicsStepSet::Substeps),
+            );
+    }
+}
+
+/// A volume centered on the entity that overrides gravity for the bodies inside it.
+#[derive(Component, Reflect, Default, Debug, Clone, Copy)]
+#[reflect(Component)]
+pub struct GravityZone {
+    pub shape: ZoneShape,
+    pub gravity: ZoneGravity,
+    /// Where zones overlap, the one with the highest priority wins.
+    pub priority: i32,
+}
+
+#[derive(Reflect, Debug, Clone, Copy)]
+pub enum ZoneShape {
+    Box { half_extents: Vec3 },
+    Sphere { radius: f32 },
+}
+
+impl Default for ZoneShape {
+    fn default() -> Self {
+        ZoneShape::Sphere { radius: 1.0 }
+    }
+}
+
+impl ZoneShape {
+    pub fn contains(&self, zone: &GlobalTransform, point: Vec3) -> bool {
+        let local = zone.affine().inverse().transform_point3(point);
+        match *self {
+            ZoneShape::Box { half_extents } => local.abs().cmple(half_extents).all(),
+            ZoneShape::Sphere { radius } => local.length() <= radius,
+        }
+    }
+}
+
+#[derive(Reflect, Default, Debug, Clone, Copy)]
+pub enum ZoneGravity {
+    #[default]
+    Zero,
+    /// A constant acceleration, in the zone's frame, so tilting the zone tilts its gravity.
+    Directional(Vec3),
+    /// An acceleration of this magnitude towards the zone's center, like around an asteroid.
+    Radial(f32),
+}
+
+impl ZoneGravity {
+    /// The acceleration at `point`, in world space.
+    pub fn at(&self, zone: &GlobalTransform, point: Vec3) -> Vec3 {
+        match *self {
+            ZoneGravity::Zero => Vec3::ZERO,
+            ZoneGravity::Directional(acceleration) => {
+                zone.compute_transform().rotation * acceleration
+            }
+            ZoneGravity::Radial(acceleration) => {
+                (zone.translation() - point).normalize_or_zero() * acceleration
+            }
+        }
+    }
+}
+
+/// Scales the gravity a body feels, whether it's the global gravity or a zone's.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component)]
+pub struct BodyGravityScale(pub f32);
+
+impl Default for BodyGravityScale {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+/// The force [`apply_local_gravity`] has added to the body's [`ExternalForce`], so it can be
+/// taken back out when the body's gravity changes.
+#[derive(Component, Reflect, Default, Debug, Clone, Copy)]
+#[reflect(Component)]
+pub struct LocalGravityForce(pub Vec3);
+
+// Hands follow their controllers and already compensate for the global gravity, so leave them be.
+// External forces persist between steps and may hold other systems' forces too, so only the change
+// in this body's gravity force is added.
+#[allow(clippy::type_complexity)]
+fn apply_local_gravity(
+    mut commands: Commands,
+    mut bodies: Query<
+        (
+            Entity,
+            &RigidBody,
+            &Position,
+            &Mass,
+            Option<&BodyGravityScale>,
+            Option<&mut LocalGravityForce>,
+            &mut ExternalForce,
+        ),
+        Without<VelocityTracked>,
+    >,
+    zones: Query<(&GravityZone, &GlobalTransform)>,
+    gravity: Res<Gravity>,
+) {
+    for (entity, rb, position, mass, scale, applied, mut external_force) in bodies.iter_mut() {
+        let local_gravity = zones
+            .iter()
+            .filter(|(zone, zone_transform)| zone.shape.contains(zone_transform, position.0))
+            .max_by_key(|(zone, _)| zone.priority)
+            .map(|(zone, zone_transform)| zone.gravity.at(zone_transform, position.0));
+        let scale = scale.map_or(1.0, |scale| scale.0);
+
+        // Outside every zone and at the default scale, physics' own gravity is already right
+        let force = match (rb.is_dynamic(), local_gravity) {
+            (false, _) => Vec3::ZERO,
+            (true, None) if scale == 1.0 => Vec3::ZERO,
+            (true, local_gravity) => {
+                (local_gravity.unwrap_or(gravity.0) * scale - gravity.0) * mass.0
+            }
+        };
+        let previous = applied.as_ref().map_or(Vec3::ZERO, |applied| applied.0);
+        if force == previous {
+            continue;
+        }
+
+        external_force.apply_force(force - previous);
+        match (applied, force == Vec3::ZERO) {
+            (Some(_), true) => {
+                commands.entity(entity).remove::<LocalGravityForce>();
+            }
+            (Some(mut applied), false) => applied.0 = force,
+            (None, _) => {
+                commands.entity(entity).insert(LocalGravityForce(force));
+            }
+        }
+    }
+}
@@ -1,19 +1,50 @@
-use bevy::prelude::*;
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
 use bevy_oxr::{
     input::XrInput,
     resources::{XrFrameState, XrInstance, XrSession},
-    xr_input::{oculus_touch::OculusController, Hand},
+    xr_input::{
+        oculus_touch::OculusController,
+        trackers::{OpenXRLeftController, OpenXRRightController},
+        Hand,
+    },
 };
 
-pub struct InputPlugin;
+use crate::vr_hands::tracking_state::TrackingState;
 
-impl Plugin for InputPlugin {
+pub struct InputPlugin<L = Update> {
+    /// The schedule [`InputSet`] runs in. Put it in the schedule of whatever reads the input, so
+    /// the readers can be ordered after it.
+    pub schedule: L,
+}
+
+impl<L: ScheduleLabel + Clone> InputPlugin<L> {
+    pub fn new(schedule: L) -> Self {
+        Self { schedule }
+    }
+}
+
+impl Default for InputPlugin {
+    fn default() -> Self {
+        Self::new(Update)
+    }
+}
+
+impl<L: ScheduleLabel + Clone> Plugin for InputPlugin<L> {
     fn build(&self, app: &mut App) {
         app.insert_resource::<InputState>(InputState::default())
-            .add_systems(Update, update_input_state);
+            .add_systems(
+                self.schedule.clone(),
+                (update_input_state, update_controller_tracking_states)
+                    .chain()
+                    .in_set(InputSet),
+            );
     }
 }
 
+/// Updates [`InputState`] from the controllers. Read it after this set.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InputSet;
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct TouchableButton {
     pub pressed: bool,
@@ -52,8 +83,16 @@ pub struct InputState {
     pub menu_button: TouchableButton,
     pub left_thumbstick: TouchableThumbstick,
     pub right_thumbstick: TouchableThumbstick,
+    /// Whether the runtime currently reports a valid pose for each controller.
+    pub left_tracked: bool,
+    pub right_tracked: bool,
 }
 
+// SpaceLocationFlags ORIENTATION_VALID | POSITION_VALID | ORIENTATION_TRACKED | POSITION_TRACKED.
+// An idle controller can keep reporting a valid but inferred pose, so it has to be tracked too.
+// bevy_oxr doesn't re-export the openxr it was built against, so its flag type can't be named here.
+const POSE_TRACKED: u64 = 0b1111;
+
 fn update_input_state(
     oculus_controller: Res<OculusController>,
     frame_state: Res<XrFrameState>,
@@ -66,17 +105,23 @@ fn update_input_state(
     let frame_state = *frame_state.lock().unwrap();
     let controller = oculus_controller.get_ref(&instance, &session, &frame_state, &xr_input);
 
+    let tracked = |hand| {
+        controller.grip_space(hand).0.location_flags.into_raw() & POSE_TRACKED == POSE_TRACKED
+    };
+    input_state.left_tracked = tracked(Hand::Left);
+    input_state.right_tracked = tracked(Hand::Right);
+
     // New code
     input_state.left_trigger.prev_value = input_state.left_trigger.value;
     input_state.left_trigger.value = controller.trigger(Hand::Left);
     input_state.left_trigger.just_touched =
-        input_state.left_trigger.touched == false && controller.trigger_touched(Hand::Left);
+        !input_state.left_trigger.touched && controller.trigger_touched(Hand::Left);
     input_state.left_trigger.touched = controller.trigger_touched(Hand::Left);
 
     input_state.right_trigger.prev_value = input_state.right_trigger.value;
     input_state.right_trigger.value = controller.trigger(Hand::Right);
     input_state.right_trigger.just_touched =
-        input_state.right_trigger.touched == false && controller.trigger_touched(Hand::Right);
+        !input_state.right_trigger.touched && controller.trigger_touched(Hand::Right);
     input_state.right_trigger.touched = controller.trigger_touched(Hand::Right);
 
     input_state.left_grip.prev_value = input_state.left_grip.value;
@@ -89,43 +134,39 @@ fn update_input_state(
     input_state.right_grip.just_touched = false; // TODO - implement grip touch
     input_state.right_grip.touched = false; // TODO - implement grip touch
 
-    input_state.a_button.just_pressed =
-        input_state.a_button.pressed == false && controller.a_button();
+    input_state.a_button.just_pressed = !input_state.a_button.pressed && controller.a_button();
     input_state.a_button.pressed = controller.a_button();
     input_state.a_button.just_touched =
-        input_state.a_button.touched == false && controller.a_button_touched();
+        !input_state.a_button.touched && controller.a_button_touched();
     input_state.a_button.touched = controller.a_button_touched();
 
-    input_state.b_button.just_pressed =
-        input_state.b_button.pressed == false && controller.b_button();
+    input_state.b_button.just_pressed = !input_state.b_button.pressed && controller.b_button();
     input_state.b_button.pressed = controller.b_button();
     input_state.b_button.just_touched =
-        input_state.b_button.touched == false && controller.b_button_touched();
+        !input_state.b_button.touched && controller.b_button_touched();
     input_state.b_button.touched = controller.b_button_touched();
 
-    input_state.x_button.just_pressed =
-        input_state.x_button.pressed == false && controller.x_button();
+    input_state.x_button.just_pressed = !input_state.x_button.pressed && controller.x_button();
     input_state.x_button.pressed = controller.x_button();
     input_state.x_button.just_touched =
-        input_state.x_button.touched == false && controller.x_button_touched();
+        !input_state.x_button.touched && controller.x_button_touched();
     input_state.x_button.touched = controller.x_button_touched();
 
-    input_state.y_button.just_pressed =
-        input_state.y_button.pressed == false && controller.y_button();
+    input_state.y_button.just_pressed = !input_state.y_button.pressed && controller.y_button();
     input_state.y_button.pressed = controller.y_button();
     input_state.y_button.just_touched =
-        input_state.y_button.touched == false && controller.y_button_touched();
+        !input_state.y_button.touched && controller.y_button_touched();
     input_state.y_button.touched = controller.y_button_touched();
 
     input_state.menu_button.just_pressed =
-        input_state.menu_button.pressed == false && controller.menu_button();
+        !input_state.menu_button.pressed && controller.menu_button();
     input_state.menu_button.pressed = controller.menu_button();
 
     input_state.left_thumbstick.just_clicked =
-        input_state.left_thumbstick.clicked == false && controller.thumbstick(Hand::Left).click;
+        !input_state.left_thumbstick.clicked && controller.thumbstick(Hand::Left).click;
     input_state.left_thumbstick.clicked = controller.thumbstick(Hand::Left).click;
     input_state.left_thumbstick.just_touched =
-        input_state.left_thumbstick.touched == false && controller.thumbstick_touch(Hand::Left);
+        !input_state.left_thumbstick.touched && controller.thumbstick_touch(Hand::Left);
     input_state.left_thumbstick.touched = controller.thumbstick_touch(Hand::Left);
     input_state.left_thumbstick.position = Vec2::new(
         controller.thumbstick(Hand::Left).x,
@@ -133,13 +174,33 @@ fn update_input_state(
     );
 
     input_state.right_thumbstick.just_clicked =
-        input_state.right_thumbstick.clicked == false && controller.thumbstick(Hand::Right).click;
+        !input_state.right_thumbstick.clicked && controller.thumbstick(Hand::Right).click;
     input_state.right_thumbstick.clicked = controller.thumbstick(Hand::Right).click;
     input_state.right_thumbstick.just_touched =
-        input_state.right_thumbstick.touched == false && controller.thumbstick_touch(Hand::Right);
+        !input_state.right_thumbstick.touched && controller.thumbstick_touch(Hand::Right);
     input_state.right_thumbstick.touched = controller.thumbstick_touch(Hand::Right);
     input_state.right_thumbstick.position = Vec2::new(
         controller.thumbstick(Hand::Right).x,
         controller.thumbstick(Hand::Right).y,
     );
 }
+
+fn update_controller_tracking_states(
+    input_state: Res<InputState>,
+    mut left_controllers: Query<&mut TrackingState, With<OpenXRLeftController>>,
+    mut right_controllers: Query<
+        &mut TrackingState,
+        (With<OpenXRRightController>, Without<OpenXRLeftController>),
+    >,
+) {
+    let state = |tracked| match tracked {
+        true => TrackingState::Tracked,
+        false => TrackingState::Lost,
+    };
+    for mut tracking_state in left_controllers.iter_mut() {
+        tracking_state.set_if_neq(state(input_state.left_tracked));
+    }
+    for mut tracking_state in right_controllers.iter_mut() {
+        tracking_state.set_if_neq(state(input_state.right_tracked));
+    }
+}
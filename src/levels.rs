@@ -0,0 +1,473 @@
+//! Levels built from named scenes in the asset libraries, with fading transitions between them.
+//! The levels are listed in the asset manifest, next to the libraries they use.
+
+use bevy::prelude::*;
+use bevy_scene_hook::{HookedSceneBundle, SceneHook};
+use serde::Deserialize;
+
+use crate::{
+    assets::{AssetLib, AssetState},
+    bounds::DespawnObject,
+    gltf_physics::{BodyKind, ColliderShape, FallbackPhysics, PhysicsBody},
+    scene::{CurrentSceneDescription, Described},
+    scene_description::transform_from,
+    sockets::add_anchors,
+};
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<LevelState>()
+            .init_resource::<Levels>()
+            .init_resource::<Fade>()
+            .add_event::<SwitchLevelEvent>()
+            .add_event::<ResetLevelEvent>()
+            .add_systems(
+                OnEnter(AssetState::Loaded),
+                (load_levels, start_first_level).chain(),
+            )
+            .add_systems(OnEnter(AssetState::Failed), show_failure)
+            .add_systems(
+                Update,
+                (
+                    (request_level_switches, request_level_resets)
+                        .run_if(in_state(LevelState::Playing)),
+                    fade_out.run_if(in_state(LevelState::FadingOut)),
+                    fade_in.run_if(in_state(LevelState::FadingIn)),
+                    (add_fade_spheres, update_fade_spheres),
+                )
+                    .chain(),
+            )
+            .add_systems(
+                OnEnter(LevelState::Switching),
+                (
+                    // Nothing to replace when the first level starts, and a world loaded from a
+                    // save is already there
+                    despawn_current_level.run_if(any_with_component::<LevelEntity>()),
+                    spawn_next_level,
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(States, Clone, Copy, Eq, PartialEq, Debug, Default, Hash)]
+pub enum LevelState {
+    /// Waiting for the asset libraries to load, or they failed to.
+    #[default]
+    Inactive,
+    FadingOut,
+    /// Replacing the current level, for a single frame while the view is black.
+    Switching,
+    FadingIn,
+    Playing,
+    /// The asset libraries failed to load, see [`AssetLibError`](crate::assets::AssetLibError).
+    /// The view stays red until they load after all.
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Level {
+    pub name: String,
+    pub scenes: Vec<LevelScene>,
+    /// The scene description for the level's static geometry, grabbables and player rig.
+    pub description: Option<String>,
+}
+
+/// A named scene from one of the asset libraries, placed in a level.
+#[derive(Debug, Clone)]
+pub struct LevelScene {
+    pub library: String,
+    pub scene: String,
+    pub transform: Transform,
+    /// Physics to use if the library has none authored for the scene.
+    pub fallback_physics: Option<FallbackPhysics>,
+}
+
+#[derive(Resource, Debug, Clone, Default)]
+pub struct Levels {
+    pub levels: Vec<Level>,
+    /// The level that is playing, or about to be once the fade out finishes.
+    pub current: usize,
+}
+
+impl Levels {
+    /// `None` if the manifest has no levels.
+    pub fn current(&self) -> Option<&Level> {
+        self.levels.get(self.current)
+    }
+}
+
+/// A [`Level`] as the asset manifest lists it.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LevelDescription {
+    pub name: String,
+    #[serde(default)]
+    pub scenes: Vec<LevelSceneDescription>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl LevelDescription {
+    pub fn level(&self) -> Level {
+        Level {
+            name: self.name.clone(),
+            scenes: self
+                .scenes
+                .iter()
+                .map(LevelSceneDescription::level_scene)
+                .collect(),
+            description: self.description.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LevelSceneDescription {
+    pub library: String,
+    pub scene: String,
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// Euler angles in degrees, applied in XYZ order.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default)]
+    pub fallback_physics: Option<FallbackPhysicsDescription>,
+}
+
+impl LevelSceneDescription {
+    pub fn level_scene(&self) -> LevelScene {
+        LevelScene {
+            library: self.library.clone(),
+            scene: self.scene.clone(),
+            transform: transform_from(self.position, self.rotation),
+            fallback_physics: self
+                .fallback_physics
+                .map(|fallback_physics| fallback_physics.fallback_physics()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct FallbackPhysicsDescription {
+    #[serde(default)]
+    pub kind: BodyKindDescription,
+    #[serde(default = "default_density")]
+    pub density: f32,
+    pub collider: ColliderShapeDescription,
+    #[serde(default)]
+    pub collider_offset: [f32; 3],
+    #[serde(default = "default_true")]
+    pub grabbable: bool,
+}
+
+impl FallbackPhysicsDescription {
+    pub fn fallback_physics(&self) -> FallbackPhysics {
+        FallbackPhysics {
+            body: PhysicsBody {
+                kind: self.kind.body_kind(),
+                density: self.density,
+            },
+            collider: self.collider.collider_shape(),
+            collider_offset: self.collider_offset.into(),
+            grabbable: self.grabbable,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub enum BodyKindDescription {
+    #[default]
+    Dynamic,
+    Static,
+    Kinematic,
+}
+
+impl BodyKindDescription {
+    pub fn body_kind(&self) -> BodyKind {
+        match self {
+            BodyKindDescription::Dynamic => BodyKind::Dynamic,
+            BodyKindDescription::Static => BodyKind::Static,
+            BodyKindDescription::Kinematic => BodyKind::Kinematic,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum ColliderShapeDescription {
+    ConvexHull,
+    Trimesh,
+    Ball {
+        radius: f32,
+    },
+    /// A box with the given full extents.
+    Cuboid {
+        size: [f32; 3],
+    },
+}
+
+impl ColliderShapeDescription {
+    pub fn collider_shape(&self) -> ColliderShape {
+        match *self {
+            ColliderShapeDescription::ConvexHull => ColliderShape::ConvexHull,
+            ColliderShapeDescription::Trimesh => ColliderShape::Trimesh,
+            ColliderShapeDescription::Ball { radius } => ColliderShape::Ball { radius },
+            ColliderShapeDescription::Cuboid { size } => {
+                ColliderShape::Cuboid { size: size.into() }
+            }
+        }
+    }
+}
+
+fn default_density() -> f32 {
+    1000.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Fade out, replace the current level with the named one, and fade back in.
+#[derive(Event, Debug, Clone)]
+pub struct SwitchLevelEvent {
+    pub level: String,
+}
+
+/// Fade out, respawn the current level's scenes and description as they started, and fade back in.
+/// Prefab instances spawned since, or loaded from a save, stay.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ResetLevelEvent;
+
+/// How black the view is.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Fade {
+    pub alpha: f32,
+    /// Seconds to fade out, and again to fade in.
+    pub duration: f32,
+}
+
+impl Default for Fade {
+    fn default() -> Self {
+        Self {
+            // Start black, so the first level fades in
+            alpha: 1.0,
+            duration: 0.5,
+        }
+    }
+}
+
+/// Marks the root entities of the current level's scenes.
+#[derive(Component, Debug, Clone)]
+pub struct LevelEntity {
+    /// The name of the level the scene belongs to.
+    pub level: String,
+}
+
+/// A black sphere around a camera, to fade the view out with.
+#[derive(Component)]
+struct FadeSphere;
+
+// The manifest may have changed since the last time it loaded, so keep to the same level if it's
+// still there
+fn load_levels(assets: AssetLib, mut levels: ResMut<Levels>) {
+    let Some(manifest) = assets.manifest() else {
+        return;
+    };
+    let current = levels.current().map(|level| level.name.clone());
+    levels.levels = manifest
+        .levels
+        .iter()
+        .map(LevelDescription::level)
+        .collect();
+    levels.current = current
+        .and_then(|current| levels.levels.iter().position(|level| level.name == current))
+        .unwrap_or(0);
+}
+
+fn start_first_level(mut level_state: ResMut<NextState<LevelState>>) {
+    level_state.set(LevelState::Switching);
+}
+
+// Without assets there's no level to show, so make it obvious instead of leaving the view black
+fn show_failure(mut fade: ResMut<Fade>, mut level_state: ResMut<NextState<LevelState>>) {
+    fade.alpha = 1.0;
+    level_state.set(LevelState::Failed);
+}
+
+fn request_level_switches(
+    mut switch_events: EventReader<SwitchLevelEvent>,
+    mut levels: ResMut<Levels>,
+    mut level_state: ResMut<NextState<LevelState>>,
+) {
+    for event in switch_events.read() {
+        let Some(index) = levels
+            .levels
+            .iter()
+            .position(|level| level.name == event.level)
+        else {
+            warn!("No level named {}", event.level);
+            continue;
+        };
+        levels.current = index;
+        level_state.set(LevelState::FadingOut);
+    }
+}
+
+fn request_level_resets(
+    mut reset_events: EventReader<ResetLevelEvent>,
+    mut level_state: ResMut<NextState<LevelState>>,
+) {
+    if reset_events.read().count() > 0 {
+        level_state.set(LevelState::FadingOut);
+    }
+}
+
+fn fade_out(
+    mut fade: ResMut<Fade>,
+    time: Res<Time>,
+    mut level_state: ResMut<NextState<LevelState>>,
+) {
+    fade.alpha = (fade.alpha + time.delta_seconds() / fade.duration).min(1.0);
+    if fade.alpha >= 1.0 {
+        level_state.set(LevelState::Switching);
+    }
+}
+
+fn fade_in(
+    mut fade: ResMut<Fade>,
+    time: Res<Time>,
+    mut level_state: ResMut<NextState<LevelState>>,
+) {
+    fade.alpha = (fade.alpha - time.delta_seconds() / fade.duration).max(0.0);
+    if fade.alpha <= 0.0 {
+        level_state.set(LevelState::Playing);
+    }
+}
+
+// Only the level's own scenes: the described scene is rebuilt when its description is set again,
+// and prefab instances spawned while playing or loaded from a save stay where they are
+fn despawn_current_level(mut commands: Commands, level_entities: Query<Entity, With<LevelEntity>>) {
+    for entity in level_entities.iter() {
+        commands.add(DespawnObject { body: entity });
+    }
+}
+
+fn spawn_next_level(
+    mut commands: Commands,
+    levels: Res<Levels>,
+    asset_lib: AssetLib,
+    asset_server: Res<AssetServer>,
+    described: Query<Entity, With<Described>>,
+    mut level_state: ResMut<NextState<LevelState>>,
+) {
+    let Some(level) = levels.current() else {
+        warn!("The manifest has no levels");
+        level_state.set(LevelState::FadingIn);
+        return;
+    };
+    info!("Spawning level {}", level.name);
+
+    for level_scene in level.scenes.iter() {
+        let scene = match asset_lib.scene(&level_scene.library, &level_scene.scene) {
+            Ok(scene) => scene,
+            Err(error) => {
+                error!("Could not spawn {}: {}", level_scene.scene, error);
+                continue;
+            }
+        };
+        let mut root = commands.spawn((
+            HookedSceneBundle {
+                scene: SceneBundle {
+                    scene,
+                    transform: level_scene.transform,
+                    ..default()
+                },
+                hook: SceneHook::new(add_anchors),
+            },
+            LevelEntity {
+                level: level.name.clone(),
+            },
+            Name::new(level_scene.scene.clone()),
+        ));
+        if let Some(fallback_physics) = level_scene.fallback_physics {
+            root.insert(fallback_physics);
+        }
+    }
+
+    match &level.description {
+        // Setting the description respawns it, even when it's the same one
+        Some(description) => {
+            commands.insert_resource(CurrentSceneDescription(asset_server.load(description)));
+        }
+        // Otherwise the last level's described scene would stay
+        None => {
+            commands.remove_resource::<CurrentSceneDescription>();
+            for entity in described.iter() {
+                commands.add(DespawnObject { body: entity });
+            }
+        }
+    }
+
+    level_state.set(LevelState::FadingIn);
+}
+
+fn add_fade_spheres(
+    mut commands: Commands,
+    cameras: Query<Entity, Added<Camera3d>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for camera in cameras.iter() {
+        let sphere = commands
+            .spawn((
+                PbrBundle {
+                    mesh: meshes.add(
+                        shape::UVSphere {
+                            radius: 0.2,
+                            ..default()
+                        }
+                        .into(),
+                    ),
+                    material: materials.add(StandardMaterial {
+                        base_color: Color::rgba(0.0, 0.0, 0.0, 1.0),
+                        alpha_mode: AlphaMode::Blend,
+                        unlit: true,
+                        // Seen from the inside
+                        cull_mode: None,
+                        ..default()
+                    }),
+                    ..default()
+                },
+                FadeSphere,
+                Name::new("Fade Sphere"),
+            ))
+            .id();
+        commands.entity(camera).add_child(sphere);
+    }
+}
+
+fn update_fade_spheres(
+    fade: Res<Fade>,
+    level_state: Res<State<LevelState>>,
+    added: Query<(), Added<FadeSphere>>,
+    mut spheres: Query<(&Handle<StandardMaterial>, &mut Visibility), With<FadeSphere>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if !fade.is_changed() && !level_state.is_changed() && added.is_empty() {
+        return;
+    }
+    let color = match level_state.get() {
+        LevelState::Failed => Color::rgb(0.5, 0.0, 0.0),
+        _ => Color::BLACK,
+    };
+    for (material, mut visibility) in spheres.iter_mut() {
+        if let Some(material) = materials.get_mut(material) {
+            material.base_color = color.with_a(fade.alpha);
+        }
+        *visibility = match fade.alpha > 0.0 {
+            true => Visibility::Inherited,
+            false => Visibility::Hidden,
+        };
+    }
+}
@@ -2,17 +2,39 @@ use bevy::prelude::*;
 
 use bevy_oxr::{xr_input::Hand, DefaultXrPlugins};
 use bevy_xpbd_3d::prelude::*;
-use input::InputState;
-use vr_hands::grabber::{EndGrabEvent, Grabbable, StartGrabEvent};
+use construction::weld::{UnweldEvent, WeldEvent, Welded};
+use input::{InputSet, InputState};
+use levels::ResetLevelEvent;
+use prefabs::SpawnPrefabEvent;
+use serde::Deserialize;
+use vr_hands::{
+    grabber::{EndGrabEvent, Grabber, GrabberSet, GrabberState, StartGrabEvent},
+    pose_filter::PrecisionMode,
+    usable::{control_value, uses_control, Usable, UseControl},
+    velocity_tracking::VelocityTrackingSet,
+};
 
 mod assets;
+mod bounds;
+mod construction;
 mod debug;
+mod gltf_physics;
+mod gravity;
 mod input;
+mod levels;
+mod prefabs;
+mod save;
 mod scene;
-mod vr_hands;
+mod scene_description;
+mod sockets;
+pub mod vr_hands;
 
 #[bevy_main]
 fn main() {
+    // Input, hand tracking and grabbing share a schedule, so their sets can be ordered. Change it
+    // here to move them all to FixedUpdate or the physics schedule together.
+    let hands_schedule = Update;
+
     App::new()
         .add_plugins(DefaultXrPlugins)
         .add_plugins(PhysicsPlugins::default())
@@ -23,43 +45,94 @@ fn main() {
         .insert_resource(Gravity(Vec3::new(0.0, -0.1, 0.0)))
         .add_plugins(debug::DebugPlugin)
         .add_plugins(assets::AssetsPlugin)
+        .add_plugins(gltf_physics::GltfPhysicsPlugin)
         .add_plugins(scene::ScenePlugin)
-        .add_plugins(input::InputPlugin)
-        .add_plugins(vr_hands::VrHandsPlugin)
-        .add_systems(Update, (spawn_cube, start_grabs, end_grabs))
+        .add_plugins(levels::LevelPlugin)
+        .add_plugins(input::InputPlugin::new(hands_schedule.clone()))
+        .add_plugins(prefabs::PrefabPlugin)
+        .add_plugins(bounds::BoundsPlugin)
+        .add_plugins(gravity::GravityPlugin)
+        .add_plugins(save::SavePlugin)
+        .add_plugins(vr_hands::VrHandsPlugin::new(
+            hands_schedule.clone(),
+            hands_schedule.clone(),
+        ))
+        .add_plugins(vr_hands::rig::XrRigPlugin::default())
+        .add_plugins(sockets::SocketPlugin)
+        .add_plugins(construction::ConstructionPlugin)
+        .init_resource::<PrecisionModeControl>()
+        .configure_sets(hands_schedule.clone(), InputSet.before(VelocityTrackingSet))
+        .add_systems(
+            hands_schedule.clone(),
+            (spawn_cube, toggle_welds, toggle_precision_mode, reset_level).after(InputSet),
+        )
+        .add_systems(
+            hands_schedule,
+            (start_grabs, end_grabs).after(InputSet).before(GrabberSet),
+        )
         .run();
 }
 
-#[derive(PhysicsLayer)]
+#[derive(PhysicsLayer, Deserialize, Debug, Clone, Copy)]
 pub enum Layer {
     Default,
     Grabbable,
     Hand,
 }
 
-// spawn a cube when the b or y button is pressed
+/// Walks up the hierarchy from `entity` to the rigid body it belongs to.
+pub(crate) fn body_of(
+    entity: Entity,
+    rbs: &Query<(), With<RigidBody>>,
+    parents: &Query<&Parent>,
+) -> Option<Entity> {
+    std::iter::once(entity)
+        .chain(parents.iter_ancestors(entity))
+        .find(|e| rbs.get(*e).is_ok())
+}
+
+// spawn a cube when the b or y button is pressed, unless the hand holds a usable bound to it
 fn spawn_cube(
     input_state: Res<InputState>,
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    grabbers: Query<&Grabber>,
+    usables: Query<&Usable>,
+    mut spawn_events: EventWriter<SpawnPrefabEvent>,
+) {
+    let pressed = [Hand::Left, Hand::Right].into_iter().any(|hand| {
+        let just_pressed = match hand {
+            Hand::Left => input_state.y_button.just_pressed,
+            Hand::Right => input_state.b_button.just_pressed,
+        };
+        just_pressed
+            && !grabbers.iter().any(|grabber| {
+                grabber.hand == hand && uses_control(grabber, &usables, UseControl::SecondaryButton)
+            })
+    });
+    if pressed {
+        spawn_events.send(SpawnPrefabEvent {
+            prefab: "cube".to_string(),
+            transform: Transform::from_xyz(0.0, 0.5, 1.0),
+        });
+    }
+}
+
+// reset the level when both thumbsticks are clicked together with nothing in either hand, since
+// clicking while holding something welds it
+fn reset_level(
+    input_state: Res<InputState>,
+    grabbers: Query<&Grabber>,
+    usables: Query<&Usable>,
+    mut reset_events: EventWriter<ResetLevelEvent>,
 ) {
-    if input_state.b_button.just_pressed || input_state.y_button.just_pressed {
-        // cube
-        commands.spawn((
-            PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Cube { size: 0.1 })),
-                material: materials.add(Color::rgb(0.8, 0.0, 0.0).into()),
-                transform: Transform::from_xyz(0.0, 0.5, 1.0),
-                ..default()
-            },
-            ColliderDensity(1000.0),
-            RigidBody::Dynamic,
-            Collider::cuboid(0.1, 0.1, 0.1),
-            CollisionLayers::new([Layer::Grabbable, Layer::Default], [Layer::Default]),
-            Grabbable { grabbed_by: vec![] },
-            Name::new("Grabbable Cube"),
-        ));
+    let clicked = (input_state.left_thumbstick.just_clicked
+        && input_state.right_thumbstick.clicked)
+        || (input_state.right_thumbstick.just_clicked && input_state.left_thumbstick.clicked);
+    let empty_handed = grabbers.iter().all(|grabber| {
+        !matches!(grabber.state, GrabberState::Grabbed(..))
+            && !uses_control(grabber, &usables, UseControl::ThumbstickClick)
+    });
+    if clicked && empty_handed {
+        reset_events.send(ResetLevelEvent);
     }
 }
 
@@ -82,3 +155,69 @@ fn end_grabs(input_state: Res<InputState>, mut grab_events_writer: EventWriter<E
         grab_events_writer.send(EndGrabEvent { hand: Hand::Right });
     }
 }
+
+// weld or unweld the held assembly when the thumbstick of the hand holding it is clicked, unless
+// it's a usable bound to the click
+fn toggle_welds(
+    input_state: Res<InputState>,
+    grabbers: Query<&Grabber>,
+    usables: Query<&Usable>,
+    welded: Query<(), With<Welded>>,
+    mut weld_events_writer: EventWriter<WeldEvent>,
+    mut unweld_events_writer: EventWriter<UnweldEvent>,
+) {
+    for grabber in grabbers.iter() {
+        let clicked = match Hand::from(grabber.hand) {
+            Hand::Left => input_state.left_thumbstick.just_clicked,
+            Hand::Right => input_state.right_thumbstick.just_clicked,
+        };
+        let GrabberState::Grabbed(held, _) = grabber.state else {
+            continue;
+        };
+        if !clicked || uses_control(grabber, &usables, UseControl::ThumbstickClick) {
+            continue;
+        }
+
+        if welded.get(held).is_ok() {
+            unweld_events_writer.send(UnweldEvent { root: held });
+        } else {
+            weld_events_writer.send(WeldEvent { part: held });
+        }
+    }
+}
+
+/// The control held on a hand to steady it for precise placement. While the hand holds a
+/// [`Usable`] bound to the same control, the control uses that instead.
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub struct PrecisionModeControl(pub UseControl);
+
+// steady a hand for precise placement while its precision mode control is held
+#[allow(clippy::too_many_arguments)]
+fn toggle_precision_mode(
+    mut commands: Commands,
+    input_state: Res<InputState>,
+    control: Res<PrecisionModeControl>,
+    grabbers: Query<(Entity, &Grabber)>,
+    usables: Query<&Usable>,
+    precision_modes: Query<Has<PrecisionMode>>,
+    rbs: Query<(), With<RigidBody>>,
+    parents: Query<&Parent>,
+) {
+    for (grabber_entity, grabber) in grabbers.iter() {
+        let Some(hand) = body_of(grabber_entity, &rbs, &parents) else {
+            continue;
+        };
+        let squeezed = !uses_control(grabber, &usables, control.0)
+            && control_value(&input_state, grabber.hand.into(), control.0) > 0.5;
+
+        match (squeezed, precision_modes.get(hand)) {
+            (true, Ok(false)) => {
+                commands.entity(hand).insert(PrecisionMode);
+            }
+            (false, Ok(true)) => {
+                commands.entity(hand).remove::<PrecisionMode>();
+            }
+            _ => {}
+        }
+    }
+}
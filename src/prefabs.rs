@@ -0,0 +1,294 @@
+//! Named templates for grabbable objects, so everything that spawns them builds them the same way.
+
+use bevy::{
+    ecs::system::{Command, EntityCommands},
+    prelude::*,
+    utils::HashMap,
+};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    bounds::{DespawnObject, OutOfBoundsBehavior},
+    construction::weld::Welded,
+    gravity::BodyGravityScale,
+    vr_hands::grabber::{held_bodies, Grabbable, Grabber, TransferMode},
+    Layer,
+};
+
+pub struct PrefabPlugin;
+
+impl Plugin for PrefabPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PrefabInstance>()
+            .init_resource::<PrefabRegistry>()
+            .init_resource::<SpawnLimits>()
+            .init_resource::<NextSpawnOrder>()
+            .add_event::<SpawnPrefabEvent>()
+            .add_systems(Startup, register_builtin_prefabs)
+            .add_systems(
+                Update,
+                (
+                    spawn_requested_prefabs,
+                    rehydrate_prefab_instances,
+                    recycle_oldest_instances,
+                ),
+            );
+    }
+}
+
+/// A template for a grabbable object.
+#[derive(Debug, Clone)]
+pub struct Prefab {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<StandardMaterial>,
+    pub collider: Collider,
+    pub density: f32,
+    pub layers: CollisionLayers,
+    /// What happens when a second hand grabs the object.
+    pub transfer: TransferMode,
+    pub out_of_bounds: OutOfBoundsBehavior,
+    /// Once there are more instances than this, the oldest ones are despawned.
+    pub max_instances: Option<usize>,
+    pub gravity_scale: f32,
+}
+
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PrefabRegistry {
+    prefabs: HashMap<String, Prefab>,
+}
+
+impl PrefabRegistry {
+    /// Adds a prefab, replacing any prefab with the same name.
+    pub fn insert(&mut self, name: impl Into<String>, prefab: Prefab) {
+        self.prefabs.insert(name.into(), prefab);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.get(name)
+    }
+}
+
+/// Caps on the number of prefab instances, on top of each prefab's own.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct SpawnLimits {
+    /// Once there are more instances of all prefabs together than this, the oldest ones are
+    /// despawned.
+    pub max_instances: Option<usize>,
+}
+
+impl Default for SpawnLimits {
+    fn default() -> Self {
+        Self {
+            max_instances: Some(64),
+        }
+    }
+}
+
+/// The prefab an entity was spawned from.
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component)]
+pub struct PrefabInstance {
+    pub prefab: String,
+}
+
+/// When an instance was spawned, relative to the others. Instances loaded from a save don't have
+/// one, and count as older than any spawned since.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct SpawnOrder(u64);
+
+#[derive(Resource, Default)]
+struct NextSpawnOrder(u64);
+
+/// Spawns the named prefab, for spawners that don't have [`Commands`] at hand.
+#[derive(Event, Debug, Clone)]
+pub struct SpawnPrefabEvent {
+    pub prefab: String,
+    pub transform: Transform,
+}
+
+pub trait SpawnPrefabExt<'w, 's> {
+    /// Spawns the named prefab. If there's no such prefab when the command is applied, the
+    /// entity is left empty.
+    fn spawn_prefab<'a>(
+        &'a mut self,
+        prefab: impl Into<String>,
+        transform: Transform,
+    ) -> EntityCommands<'w, 's, 'a>;
+}
+
+impl<'w, 's> SpawnPrefabExt<'w, 's> for Commands<'w, 's> {
+    fn spawn_prefab<'a>(
+        &'a mut self,
+        prefab: impl Into<String>,
+        transform: Transform,
+    ) -> EntityCommands<'w, 's, 'a> {
+        let entity = self.spawn_empty().id();
+        self.add(InsertPrefab {
+            entity,
+            prefab: prefab.into(),
+            transform,
+        });
+        self.entity(entity)
+    }
+}
+
+struct InsertPrefab {
+    entity: Entity,
+    prefab: String,
+    transform: Transform,
+}
+
+impl Command for InsertPrefab {
+    fn apply(self, world: &mut World) {
+        let Some(prefab) = world
+            .resource::<PrefabRegistry>()
+            .get(&self.prefab)
+            .cloned()
+        else {
+            // Leave the entity, other commands may still be queued for it
+            error!("No prefab named {}", self.prefab);
+            return;
+        };
+        if world.get_entity(self.entity).is_none() {
+            return;
+        }
+
+        let mut next_order = world.resource_mut::<NextSpawnOrder>();
+        let order = SpawnOrder(next_order.0);
+        next_order.0 += 1;
+        world.entity_mut(self.entity).insert((
+            prefab_bundle(prefab, self.transform),
+            Name::new(self.prefab.clone()),
+            PrefabInstance {
+                prefab: self.prefab,
+            },
+            order,
+        ));
+    }
+}
+
+fn prefab_bundle(prefab: Prefab, transform: Transform) -> impl Bundle {
+    (
+        PbrBundle {
+            mesh: prefab.mesh,
+            material: prefab.material,
+            transform,
+            ..default()
+        },
+        ColliderDensity(prefab.density),
+        RigidBody::Dynamic,
+        prefab.collider,
+        prefab.layers,
+        Grabbable {
+            transfer: prefab.transfer,
+            ..default()
+        },
+        prefab.out_of_bounds,
+        BodyGravityScale(prefab.gravity_scale),
+    )
+}
+
+fn register_builtin_prefabs(
+    mut registry: ResMut<PrefabRegistry>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    registry.insert(
+        "cube",
+        Prefab {
+            mesh: meshes.add(Mesh::from(shape::Cube { size: 0.1 })),
+            material: materials.add(Color::rgb(0.8, 0.0, 0.0).into()),
+            collider: Collider::cuboid(0.1, 0.1, 0.1),
+            density: 1000.0,
+            layers: CollisionLayers::new([Layer::Grabbable, Layer::Default], [Layer::Default]),
+            transfer: TransferMode::Steal,
+            out_of_bounds: OutOfBoundsBehavior::Despawn,
+            max_instances: Some(16),
+            gravity_scale: 1.0,
+        },
+    );
+}
+
+fn spawn_requested_prefabs(
+    mut commands: Commands,
+    mut spawn_events: EventReader<SpawnPrefabEvent>,
+) {
+    for event in spawn_events.read() {
+        commands.spawn_prefab(event.prefab.clone(), event.transform);
+    }
+}
+
+// Prefab instances loaded from a saved world only have their state, so add back the rest
+#[allow(clippy::type_complexity)]
+fn rehydrate_prefab_instances(
+    mut commands: Commands,
+    instances: Query<(Entity, &PrefabInstance, &Transform, Option<&Parent>), Without<Handle<Mesh>>>,
+    welded: Query<&Welded>,
+    registry: Res<PrefabRegistry>,
+) {
+    for (entity, instance, transform, parent) in instances.iter() {
+        // Try again once the prefab is registered
+        let Some(prefab) = registry.get(&instance.prefab).cloned() else {
+            continue;
+        };
+        let mut entity_commands = commands.entity(entity);
+        entity_commands.insert(prefab_bundle(prefab, *transform));
+
+        // Parts welded to another part are part of its body
+        let is_welded_part = parent
+            .and_then(|parent| welded.get(parent.get()).ok())
+            .is_some_and(|weld| weld.parts.contains(&entity));
+        if is_welded_part {
+            entity_commands.remove::<(RigidBody, LinearVelocity, AngularVelocity)>();
+        }
+    }
+}
+
+// Despawn the oldest instances over the limits, leaving held ones alone
+fn recycle_oldest_instances(
+    mut commands: Commands,
+    limits: Res<SpawnLimits>,
+    registry: Res<PrefabRegistry>,
+    instances: Query<(Entity, &PrefabInstance, Option<&SpawnOrder>), With<RigidBody>>,
+    grabbers: Query<&Grabber>,
+) {
+    let held = held_bodies(&grabbers);
+    let mut instances: Vec<_> = instances.iter().collect();
+    instances.sort_by_key(|(_, _, order)| order.copied());
+
+    let mut recycled: Vec<Entity> = vec![];
+    for (name, prefab) in registry.prefabs.iter() {
+        let Some(max) = prefab.max_instances else {
+            continue;
+        };
+        let candidates: Vec<Entity> = instances
+            .iter()
+            .filter(|(_, instance, _)| instance.prefab == *name)
+            .map(|(entity, _, _)| *entity)
+            .collect();
+        recycled.extend(oldest_over_limit(&candidates, &held, max));
+    }
+    if let Some(max) = limits.max_instances {
+        let candidates: Vec<Entity> = instances
+            .iter()
+            .map(|(entity, _, _)| *entity)
+            .filter(|entity| !recycled.contains(entity))
+            .collect();
+        recycled.extend(oldest_over_limit(&candidates, &held, max));
+    }
+
+    for entity in recycled {
+        debug!("Recycling {:?}", entity);
+        commands.add(DespawnObject { body: entity });
+    }
+}
+
+/// The oldest of `instances` that have to go to bring their number down to `max`.
+fn oldest_over_limit(instances: &[Entity], held: &[Entity], max: usize) -> Vec<Entity> {
+    instances
+        .iter()
+        .filter(|entity| !held.contains(entity))
+        .take(instances.len().saturating_sub(max))
+        .copied()
+        .collect()
+}
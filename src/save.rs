@@ -0,0 +1,684 @@
+//! Saving the built world to a file and restoring it, including across restarts.
+//!
+//! The prefab instances the player made and the joints between them are saved whole. Everything
+//! else comes from the scene description and the level, which spawn it again every session.
+//! Saved entities hold their state, and get the rest back from their prefab when loaded.
+//!
+//! Bodies from the level's glTF scenes only have their state saved: their pose, velocity and the
+//! anchors in the same level they're socketed in. It's found again by the names leading to them in
+//! their scene, once the level has spawned them. The hands aren't saved, since their controllers
+//! won't be where they were, so grab joints and grabber states are dropped and held objects come
+//! back released.
+
+use std::{fs, path::PathBuf};
+
+use bevy::{
+    app::AppExit,
+    ecs::{
+        event::ManualEventReader,
+        system::{Command, SystemState},
+    },
+    prelude::*,
+    scene::serde::SceneDeserializer,
+    transform::TransformSystem,
+    utils::{HashMap, HashSet},
+};
+use bevy_xpbd_3d::prelude::*;
+use serde::de::DeserializeSeed;
+
+use crate::{
+    body_of,
+    bounds::{DespawnObject, ResetBody},
+    construction::{connector::Connection, weld::Welded},
+    levels::LevelEntity,
+    prefabs::PrefabInstance,
+    scene::Described,
+    sockets::{socket_into, Socketed},
+    vr_hands::{fixed_joint_2::FixedJoint2, generic_joint::GenericJoint, grabber::Grabbable},
+};
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<SavedLevelBody>()
+            .register_type::<(String, String)>()
+            .register_type::<Vec<(String, String)>>()
+            .init_resource::<SaveSettings>()
+            .add_event::<SaveWorldEvent>()
+            .add_event::<LoadWorldEvent>()
+            .add_systems(Startup, load_saved_world_on_startup)
+            .add_systems(Update, (autosave, save_world, load_world).chain())
+            // Anchors and bodies are only where they belong once their transforms have propagated
+            .add_systems(
+                PostUpdate,
+                restore_level_bodies.after(TransformSystem::TransformPropagate),
+            )
+            .add_systems(Last, save_world_on_exit);
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct SaveSettings {
+    pub path: PathBuf,
+    /// Save every this many seconds. Android can kill the app without an exit event, so this is
+    /// what keeps the world between sessions on the headset.
+    pub autosave_interval: Option<f32>,
+    pub load_on_startup: bool,
+}
+
+impl Default for SaveSettings {
+    fn default() -> Self {
+        // The app's internal storage on the headset
+        let path = match cfg!(target_os = "android") {
+            true => PathBuf::from("/data/data/com.knexer.bevy_vr_test/files/world.scn.ron"),
+            false => PathBuf::from("world.scn.ron"),
+        };
+        Self {
+            path,
+            autosave_interval: Some(30.0),
+            load_on_startup: true,
+        }
+    }
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct SaveWorldEvent;
+
+/// Stands in for a dynamic body from a level's glTF scenes, loaded from a save, until the level
+/// spawns the body again and it takes on this state.
+#[derive(Component, Reflect, Default, Debug, Clone)]
+#[reflect(Component)]
+pub struct SavedLevelBody {
+    pub level: String,
+    /// The names from the root of the body's level scene down to it, like "hamster/Hamster".
+    pub path: String,
+    /// The body's global pose.
+    pub transform: Transform,
+    pub linear_velocity: Vec3,
+    pub angular_velocity: Vec3,
+    /// The paths of the body's socketed anchorables, and of the anchors they're in.
+    pub sockets: Vec<(String, String)>,
+}
+
+/// Replaces the saved entities in the world with the ones in the save file.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct LoadWorldEvent;
+
+fn autosave(
+    settings: Res<SaveSettings>,
+    time: Res<Time>,
+    mut since_save: Local<f32>,
+    mut save_events: EventWriter<SaveWorldEvent>,
+) {
+    let Some(interval) = settings.autosave_interval else {
+        return;
+    };
+    *since_save += time.delta_seconds();
+    if *since_save >= interval {
+        *since_save = 0.0;
+        save_events.send(SaveWorldEvent);
+    }
+}
+
+fn save_world(world: &mut World, mut save_events: Local<ManualEventReader<SaveWorldEvent>>) {
+    let events = world.resource::<Events<SaveWorldEvent>>();
+    if save_events.read(events).count() == 0 {
+        return;
+    }
+    write_save(world);
+}
+
+fn save_world_on_exit(world: &mut World, mut exit_events: Local<ManualEventReader<AppExit>>) {
+    let events = world.resource::<Events<AppExit>>();
+    if exit_events.read(events).count() == 0 {
+        return;
+    }
+    write_save(world);
+}
+
+fn load_world(world: &mut World, mut load_events: Local<ManualEventReader<LoadWorldEvent>>) {
+    let events = world.resource::<Events<LoadWorldEvent>>();
+    if load_events.read(events).count() == 0 {
+        return;
+    }
+    read_save(world);
+}
+
+fn load_saved_world_on_startup(world: &mut World) {
+    let settings = world.resource::<SaveSettings>();
+    if settings.load_on_startup && settings.path.exists() {
+        read_save(world);
+    }
+}
+
+/// The entities that go into a save: prefab instances, joints with both ends among them, and level
+/// bodies loaded from a save that haven't spawned yet. Instances from the scene description, and
+/// anything welded onto them, are spawned with it again, so saving them would duplicate them every
+/// session.
+#[allow(clippy::type_complexity)]
+fn saved_entities(world: &mut World) -> Vec<Entity> {
+    let mut state: SystemState<(
+        Query<Entity, With<PrefabInstance>>,
+        Query<Entity, With<Described>>,
+        Query<&Children>,
+        Query<Entity, With<SavedLevelBody>>,
+    )> = SystemState::new(world);
+    let (instances, described, children, saved_level_bodies) = state.get(world);
+    let from_description: HashSet<Entity> = described
+        .iter()
+        .flat_map(|entity| std::iter::once(entity).chain(children.iter_descendants(entity)))
+        .collect();
+    let mut entities: Vec<Entity> = instances
+        .iter()
+        .filter(|entity| !from_description.contains(entity))
+        .collect();
+    let saved: HashSet<Entity> = entities.iter().copied().collect();
+    entities.extend(saved_level_bodies.iter());
+
+    let mut fixed_joints = world.query::<(Entity, &FixedJoint2)>();
+    let mut generic_joints = world.query::<(Entity, &GenericJoint)>();
+    let joints = fixed_joints
+        .iter(world)
+        .map(|(entity, joint)| (entity, [joint.entity1, joint.entity2]))
+        .chain(
+            generic_joints
+                .iter(world)
+                .map(|(entity, joint)| (entity, [joint.entity1, joint.entity2])),
+        );
+    // Grab joints end in a hand and socket joints in an anchor, neither of which are saved
+    entities.extend(
+        joints
+            .filter(|(_, bodies)| bodies.iter().all(|body| saved.contains(body)))
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>(),
+    );
+    entities
+}
+
+/// A body's level, and the path of names from the root of its level scene down to it. Unlike its
+/// entity, these stay the same when the level spawns it again.
+fn level_path(
+    entity: Entity,
+    roots: &Query<&LevelEntity>,
+    names: &Query<&Name>,
+    parents: &Query<&Parent>,
+) -> Option<(String, String)> {
+    let mut segments = vec![];
+    let mut current = entity;
+    loop {
+        segments.push(names.get(current).ok()?.as_str());
+        if let Ok(root) = roots.get(current) {
+            segments.reverse();
+            return Some((root.level.clone(), segments.join("/")));
+        }
+        current = parents.get(current).ok()?.get();
+    }
+}
+
+/// The entity at a [`level_path`], if its level has spawned it.
+fn find_level_path(
+    level: &str,
+    path: &str,
+    roots: &Query<(Entity, &LevelEntity, &Name)>,
+    children: &Query<&Children>,
+    names: &Query<&Name>,
+) -> Option<Entity> {
+    let mut segments = path.split('/');
+    let root_name = segments.next()?;
+    let (root, _, _) = roots
+        .iter()
+        .find(|(_, root, name)| root.level == level && name.as_str() == root_name)?;
+    segments.try_fold(root, |entity, segment| {
+        children
+            .get(entity)
+            .ok()?
+            .iter()
+            .copied()
+            .find(|child| names.get(*child).is_ok_and(|name| name.as_str() == segment))
+    })
+}
+
+/// The state of the dynamic bodies in the levels' glTF scenes.
+#[allow(clippy::type_complexity)]
+fn level_body_snapshots(world: &mut World) -> Vec<SavedLevelBody> {
+    let mut state: SystemState<(
+        Query<(
+            Entity,
+            &RigidBody,
+            &GlobalTransform,
+            Option<&LinearVelocity>,
+            Option<&AngularVelocity>,
+        )>,
+        Query<(Entity, &Socketed)>,
+        Query<&LevelEntity>,
+        Query<&Name>,
+        Query<&Parent>,
+        Query<(), With<RigidBody>>,
+    )> = SystemState::new(world);
+    let (bodies, socketed, roots, names, parents, rbs) = state.get(world);
+
+    bodies
+        .iter()
+        .filter(|(_, rb, _, _, _)| rb.is_dynamic())
+        .filter_map(|(body, _, transform, linear_velocity, angular_velocity)| {
+            let (level, path) = level_path(body, &roots, &names, &parents)?;
+            let sockets = socketed
+                .iter()
+                .filter(|(anchorable, _)| body_of(*anchorable, &rbs, &parents) == Some(body))
+                .filter_map(|(anchorable, socketed)| {
+                    let (_, anchorable) = level_path(anchorable, &roots, &names, &parents)?;
+                    let (anchor_level, anchor) =
+                        level_path(socketed.anchor, &roots, &names, &parents)?;
+                    (anchor_level == level).then_some((anchorable, anchor))
+                })
+                .collect();
+            Some(SavedLevelBody {
+                level,
+                path,
+                transform: transform.compute_transform(),
+                linear_velocity: linear_velocity.map_or(Vec3::ZERO, |velocity| velocity.0),
+                angular_velocity: angular_velocity.map_or(Vec3::ZERO, |velocity| velocity.0),
+                sockets,
+            })
+        })
+        .collect()
+}
+
+fn write_save(world: &mut World) {
+    let mut entities = saved_entities(world);
+    // Level bodies are saved through stand-ins, which leave the rest of their glTF scene behind.
+    // Ones still waiting for their level from the last load keep waiting, unless it has spawned.
+    let snapshots = level_body_snapshots(world);
+    entities.retain(|entity| {
+        !world.get::<SavedLevelBody>(*entity).is_some_and(|pending| {
+            snapshots
+                .iter()
+                .any(|saved| saved.level == pending.level && saved.path == pending.path)
+        })
+    });
+    let stand_ins: Vec<Entity> = snapshots
+        .into_iter()
+        .map(|saved| world.spawn(saved).id())
+        .collect();
+    entities.extend(stand_ins.iter().copied());
+
+    let scene = DynamicSceneBuilder::from_world(world)
+        .deny_all()
+        .allow::<Name>()
+        .allow::<Transform>()
+        .allow::<Parent>()
+        .allow::<Children>()
+        .allow::<LinearVelocity>()
+        .allow::<AngularVelocity>()
+        .allow::<PrefabInstance>()
+        .allow::<Grabbable>()
+        .allow::<Welded>()
+        .allow::<FixedJoint2>()
+        .allow::<GenericJoint>()
+        .allow::<Connection>()
+        .allow::<SavedLevelBody>()
+        .extract_entities(entities.into_iter())
+        .build();
+    for stand_in in stand_ins {
+        world.despawn(stand_in);
+    }
+
+    let type_registry = world.resource::<AppTypeRegistry>();
+    let serialized = match scene.serialize_ron(type_registry) {
+        Ok(serialized) => serialized,
+        Err(error) => {
+            error!("Could not serialize world: {}", error);
+            return;
+        }
+    };
+
+    let path = &world.resource::<SaveSettings>().path;
+    if let Some(directory) = path.parent() {
+        let _ = fs::create_dir_all(directory);
+    }
+    match fs::write(path, serialized) {
+        Ok(()) => info!("Saved world to {}", path.display()),
+        Err(error) => error!("Could not write {}: {}", path.display(), error),
+    }
+}
+
+fn read_save(world: &mut World) {
+    let path = world.resource::<SaveSettings>().path.clone();
+    let serialized = match fs::read_to_string(&path) {
+        Ok(serialized) => serialized,
+        Err(error) => {
+            error!("Could not read {}: {}", path.display(), error);
+            return;
+        }
+    };
+
+    let scene = {
+        let type_registry = world.resource::<AppTypeRegistry>().read();
+        let deserializer = SceneDeserializer {
+            type_registry: &type_registry,
+        };
+        let mut ron_deserializer = match ron::de::Deserializer::from_str(&serialized) {
+            Ok(ron_deserializer) => ron_deserializer,
+            Err(error) => {
+                error!("Could not parse {}: {}", path.display(), error);
+                return;
+            }
+        };
+        match deserializer.deserialize(&mut ron_deserializer) {
+            Ok(scene) => scene,
+            Err(error) => {
+                error!("Could not load {}: {}", path.display(), error);
+                return;
+            }
+        }
+    };
+
+    // Replace what's there, so loading twice doesn't duplicate everything. Grabs, sockets and
+    // connections on the replaced entities let go first, so nothing points at them afterwards.
+    for entity in saved_entities(world) {
+        DespawnObject { body: entity }.apply(world);
+    }
+
+    // Entity references, like joint ends and welded parts, are remapped to the new entities here
+    let mut entity_map = HashMap::default();
+    if let Err(error) = scene.write_to_world(world, &mut entity_map) {
+        error!("Could not load {}: {}", path.display(), error);
+        return;
+    }
+
+    // The hands that held objects when saving aren't around anymore
+    let mut grabbables: SystemState<Query<&mut Grabbable>> = SystemState::new(world);
+    let mut grabbables = grabbables.get_mut(world);
+    for loaded in entity_map.values() {
+        if let Ok(mut grabbable) = grabbables.get_mut(*loaded) {
+            grabbable.grabbed_by.clear();
+        }
+    }
+    info!(
+        "Loaded {} entities from {}",
+        entity_map.len(),
+        path.display()
+    );
+}
+
+// Level bodies take on their saved state once their level has spawned them, and their anchors
+fn restore_level_bodies(
+    mut commands: Commands,
+    saved_bodies: Query<(Entity, &SavedLevelBody)>,
+    roots: Query<(Entity, &LevelEntity, &Name)>,
+    children: Query<&Children>,
+    names: Query<&Name>,
+    rbs: Query<(), With<RigidBody>>,
+    parents: Query<&Parent>,
+) {
+    for (stand_in, saved) in saved_bodies.iter() {
+        let find = |path: &str| find_level_path(&saved.level, path, &roots, &children, &names);
+        let Some(body) = find(&saved.path).filter(|body| rbs.contains(*body)) else {
+            continue;
+        };
+        let Some(sockets) = saved
+            .sockets
+            .iter()
+            .map(|(anchorable, anchor)| {
+                let anchor =
+                    find(anchor).filter(|anchor| body_of(*anchor, &rbs, &parents).is_some());
+                Some((find(anchorable)?, anchor?))
+            })
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+
+        commands.add(RestoreLevelBody {
+            body,
+            saved: saved.clone(),
+            sockets,
+        });
+        commands.entity(stand_in).despawn();
+    }
+}
+
+struct RestoreLevelBody {
+    body: Entity,
+    saved: SavedLevelBody,
+    /// The body's anchorables, and the anchors they go back into.
+    sockets: Vec<(Entity, Entity)>,
+}
+
+impl Command for RestoreLevelBody {
+    fn apply(self, world: &mut World) {
+        if world.get_entity(self.body).is_none() {
+            return;
+        }
+        ResetBody {
+            body: self.body,
+            transform: self.saved.transform,
+        }
+        .apply(world);
+        let mut body = world.entity_mut(self.body);
+        if let Some(mut linear_velocity) = body.get_mut::<LinearVelocity>() {
+            linear_velocity.0 = self.saved.linear_velocity;
+        }
+        if let Some(mut angular_velocity) = body.get_mut::<AngularVelocity>() {
+            angular_velocity.0 = self.saved.angular_velocity;
+        }
+        for (anchorable, anchor) in self.sockets {
+            if !socket_into(world, anchorable, anchor) {
+                warn!("Could not return {} to its saved anchor", self.saved.path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        construction::weld::MovedJoint,
+        sockets::{Anchor, Anchorable},
+        vr_hands::{fixed_joint_2::JointSpring, grabber::TransferMode},
+    };
+
+    fn app_saving_to(name: &str) -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, TransformPlugin, HierarchyPlugin))
+            .register_type::<LinearVelocity>()
+            .register_type::<AngularVelocity>()
+            .register_type::<Rotation>()
+            .register_type::<PrefabInstance>()
+            .register_type::<Grabbable>()
+            .register_type::<TransferMode>()
+            .register_type::<Vec<Entity>>()
+            .register_type::<Welded>()
+            .register_type::<MovedJoint>()
+            .register_type::<Vec<MovedJoint>>()
+            .register_type::<(FixedJoint2, Connection)>()
+            .register_type::<Vec<(FixedJoint2, Connection)>>()
+            .register_type::<FixedJoint2>()
+            .register_type::<JointSpring>()
+            .register_type::<Option<JointSpring>>()
+            .register_type::<Connection>()
+            .register_type::<SavedLevelBody>()
+            .register_type::<(String, String)>()
+            .register_type::<Vec<(String, String)>>()
+            .insert_resource(SaveSettings {
+                path: std::env::temp_dir().join(format!("{}-{}.scn.ron", name, std::process::id())),
+                autosave_interval: None,
+                load_on_startup: false,
+            });
+        app
+    }
+
+    fn instance(world: &mut World, x: f32) -> Entity {
+        world
+            .spawn((
+                PrefabInstance {
+                    prefab: "cube".to_string(),
+                },
+                Grabbable::default(),
+                TransformBundle::from_transform(Transform::from_xyz(x, 1.0, 0.0)),
+            ))
+            .id()
+    }
+
+    fn instances(world: &mut World) -> Vec<(Entity, f32)> {
+        let mut instances = world.query_filtered::<(Entity, &Transform), With<PrefabInstance>>();
+        let mut instances: Vec<(Entity, f32)> = instances
+            .iter(world)
+            .map(|(entity, transform)| (entity, transform.translation.x))
+            .collect();
+        instances.sort_by(|a, b| a.1.total_cmp(&b.1));
+        instances
+    }
+
+    #[test]
+    fn round_trips_instances_and_joints() {
+        let mut app = app_saving_to("round-trip");
+        let world = &mut app.world;
+        let first = instance(world, 1.0);
+        let second = instance(world, 2.0);
+        world.spawn(FixedJoint2::new(first, second));
+        // A hand isn't saved, so neither is the joint holding the first instance
+        let hand = world.spawn(TransformBundle::default()).id();
+        world.spawn(FixedJoint2::new(hand, first));
+        world
+            .get_mut::<Grabbable>(first)
+            .unwrap()
+            .grabbed_by
+            .push(hand);
+
+        write_save(world);
+        read_save(world);
+
+        let loaded = instances(world);
+        assert_eq!(
+            loaded.iter().map(|(_, x)| *x).collect::<Vec<_>>(),
+            [1.0, 2.0]
+        );
+        assert!(loaded
+            .iter()
+            .all(|(entity, _)| ![first, second].contains(entity)));
+        let mut joints = world.query::<&FixedJoint2>();
+        let joints: Vec<[Entity; 2]> = joints
+            .iter(world)
+            .map(|joint| [joint.entity1, joint.entity2])
+            .collect();
+        // The hand's joint went with the instance it held, the saved one was remapped to the
+        // loaded instances
+        assert_eq!(joints, [[loaded[0].0, loaded[1].0]]);
+        assert!(world
+            .get::<Grabbable>(loaded[0].0)
+            .unwrap()
+            .grabbed_by
+            .is_empty());
+
+        let _ = fs::remove_file(&world.resource::<SaveSettings>().path);
+    }
+
+    #[test]
+    fn skips_described_instances() {
+        let mut app = app_saving_to("described");
+        let world = &mut app.world;
+        let saved = instance(world, 1.0);
+        let described = instance(world, 2.0);
+        world.entity_mut(described).insert(Described);
+        // Welded onto the described instance
+        let welded = instance(world, 3.0);
+        world.entity_mut(described).add_child(welded);
+        world.spawn(FixedJoint2::new(described, welded));
+
+        // Loading twice doesn't pile up copies either
+        write_save(world);
+        read_save(world);
+        read_save(world);
+
+        let loaded: Vec<f32> = instances(world).iter().map(|(_, x)| *x).collect();
+        assert_eq!(loaded, [1.0, 2.0, 3.0]);
+        assert!(world.get_entity(saved).is_none());
+        assert!(world.get_entity(described).is_some());
+        assert!(world.get_entity(welded).is_some());
+        let mut joints = world.query::<&FixedJoint2>();
+        assert_eq!(joints.iter(world).count(), 1);
+
+        let _ = fs::remove_file(&world.resource::<SaveSettings>().path);
+    }
+
+    // A level scene with a dynamic body and an anchor, the body socketed into it when `socketed`
+    fn level_scene(world: &mut World, socketed: bool) -> (Entity, Entity) {
+        let root = world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(1.0, 0.0, 0.0)),
+                LevelEntity {
+                    level: "test".to_string(),
+                },
+                Name::new("shelf"),
+            ))
+            .id();
+        let anchor = world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 1.0, 0.0)),
+                RigidBody::Static,
+                Anchor::default(),
+                Name::new("Anchor"),
+            ))
+            .id();
+        let body = world
+            .spawn((
+                TransformBundle::default(),
+                RigidBody::Dynamic,
+                LinearVelocity::default(),
+                AngularVelocity::default(),
+                Anchorable::default(),
+                Name::new("Hamster"),
+            ))
+            .id();
+        world.entity_mut(root).push_children(&[anchor, body]);
+        if socketed {
+            world.get_mut::<Transform>(body).unwrap().translation = Vec3::Y;
+            world.get_mut::<LinearVelocity>(body).unwrap().0 = Vec3::X;
+            let joint = world.spawn(FixedJoint2::new(anchor, body)).id();
+            world.entity_mut(body).insert(Socketed { anchor, joint });
+            world.get_mut::<Anchor>(anchor).unwrap().occupant = Some(body);
+        }
+        (root, body)
+    }
+
+    #[test]
+    fn restores_level_bodies_once_respawned() {
+        let mut app = app_saving_to("level-bodies");
+        app.add_systems(
+            PostUpdate,
+            restore_level_bodies.after(TransformSystem::TransformPropagate),
+        );
+        let (root, _) = level_scene(&mut app.world, true);
+        app.update();
+
+        write_save(&mut app.world);
+        assert!(app
+            .world
+            .query::<&SavedLevelBody>()
+            .iter(&app.world)
+            .next()
+            .is_none());
+        // Like resetting the level before loading
+        DespawnObject { body: root }.apply(&mut app.world);
+        let (_, body) = level_scene(&mut app.world, false);
+        read_save(&mut app.world);
+        app.update();
+
+        let world = &mut app.world;
+        assert_eq!(world.get::<Transform>(body).unwrap().translation, Vec3::Y);
+        assert_eq!(world.get::<LinearVelocity>(body).unwrap().0, Vec3::X);
+        let anchor = world.get::<Socketed>(body).unwrap().anchor;
+        assert_eq!(world.get::<Anchor>(anchor).unwrap().occupant, Some(body));
+        assert_eq!(world.query::<&FixedJoint2>().iter(world).count(), 1);
+        assert!(world
+            .query::<&SavedLevelBody>()
+            .iter(world)
+            .next()
+            .is_none());
+
+        let _ = fs::remove_file(&world.resource::<SaveSettings>().path);
+    }
+}
@@ -1,15 +1,13 @@
 use bevy::prelude::*;
-use bevy_oxr::xr_input::{
-    trackers::{OpenXRController, OpenXRLeftController, OpenXRRightController, OpenXRTracker},
-    Hand,
-};
-use bevy_scene_hook::{HookPlugin, HookedSceneBundle, SceneHook};
+use bevy_scene_hook::HookPlugin;
 use bevy_xpbd_3d::prelude::*;
 
 use crate::{
-    assets::{AssetLib, AssetState},
-    vr_hands::grabber::{Grabbable, Grabber, GrabberState},
-    vr_hands::velocity_tracking::VelocityTracked,
+    bounds::{DespawnObject, KillVolume},
+    gravity::GravityZone,
+    prefabs::{Prefab, PrefabRegistry, SpawnPrefabExt},
+    scene_description::{transform_from, SceneDescription, SceneDescriptionPlugin},
+    vr_hands::rig::RigConfig,
     Layer,
 };
 
@@ -18,227 +16,162 @@ pub struct ScenePlugin;
 impl Plugin for ScenePlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(HookPlugin)
-            .add_systems(Startup, (setup, spawn_player))
-            .add_systems(OnEnter(AssetState::Loaded), spawn_test_gltf)
-            .add_systems(Update, log_if_anchorable_found)
-            .register_type::<Anchorable>()
-            .register_type::<Anchor>();
+            .add_plugins(SceneDescriptionPlugin)
+            .add_systems(
+                Update,
+                spawn_described_scene.run_if(resource_exists::<CurrentSceneDescription>()),
+            );
     }
 }
 
-/// set up a simple 3D scene
-fn setup(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-    // plane
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(shape::Plane::from_size(5.0).into()),
-            material: materials.add(Color::rgb(0.3, 0.5, 0.3).into()),
-            transform: Transform::from_xyz(0.0, -0.5, 0.0),
-            ..default()
-        },
-        RigidBody::Static,
-        Collider::halfspace(Vec3::Y),
-        CollisionLayers::new([Layer::Default], [Layer::Default]),
-    ));
-    // cube
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::Cube { size: 0.1 })),
-            material: materials.add(Color::rgb(0.8, 0.0, 0.0).into()),
-            transform: Transform::from_xyz(0.0, 0.5, 1.0),
-            ..default()
-        },
-        ColliderDensity(1000.0),
-        RigidBody::Dynamic,
-        Collider::cuboid(0.1, 0.1, 0.1),
-        CollisionLayers::new([Layer::Grabbable, Layer::Default], [Layer::Default]),
-        Grabbable { grabbed_by: vec![] },
-        Name::new("Grabbable Cube"),
-    ));
-    // light
-    commands.spawn(PointLightBundle {
-        point_light: PointLight {
-            intensity: 1500.0,
-            shadows_enabled: true,
-            ..default()
-        },
-        transform: Transform::from_xyz(4.0, 8.0, 4.0),
-        ..default()
-    });
-}
+/// The scene description the level is built from. Set by the current level, and replacing it
+/// rebuilds the scene.
+#[derive(Resource)]
+pub struct CurrentSceneDescription(pub Handle<SceneDescription>);
 
-fn spawn_player(
+/// Marks entities spawned from the current scene description, which are replaced when it changes.
+#[derive(Component)]
+pub struct Described;
+
+// (Re)build the scene whenever its description is replaced, finishes loading or is edited on disk
+#[allow(clippy::too_many_arguments)]
+fn spawn_described_scene(
     mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<SceneDescription>>,
+    current: Res<CurrentSceneDescription>,
+    descriptions: Res<Assets<SceneDescription>>,
+    described: Query<Entity, With<Described>>,
+    mut prefab_registry: ResMut<PrefabRegistry>,
+    mut gravity: ResMut<Gravity>,
+    mut rig_config: ResMut<RigConfig>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    //left hand
-    let left_controller = commands
-        .spawn((
-            OpenXRLeftController,
-            OpenXRController,
-            OpenXRTracker,
-            SpatialBundle::default(),
-            Name::new("Left Controller"),
-        ))
-        .id();
-    commands
-        .spawn((
-            VelocityTracked {
-                follow_target: left_controller,
-                follow_strength: 30.0,
-                max_distance: 0.75,
-                rotation_follow_strength: 30.0,
-            },
-            RigidBody::Dynamic,
-            ColliderDensity(1000.0),
-            Collider::cuboid(0.1, 0.05, 0.1),
-            CollisionLayers::new([Layer::Hand, Layer::Default], [Layer::Default]),
+    let loaded = asset_events
+        .read()
+        .filter(|event| match event {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => {
+                *id == current.0.id()
+            }
+            _ => false,
+        })
+        .count()
+        > 0;
+    if !loaded && !current.is_changed() {
+        return;
+    }
+    let Some(description) = descriptions.get(&current.0) else {
+        return;
+    };
+
+    info!("Spawning scene from description");
+    // Grabbed or socketed things let go first, so nothing keeps joints or grabs on the old ones
+    for entity in described.iter() {
+        commands.add(DespawnObject { body: entity });
+    }
+
+    if let Some(acceleration) = description.gravity {
+        gravity.0 = acceleration.into();
+    }
+
+    for geometry in description.static_geometry.iter() {
+        let mut entity = commands.spawn((
             PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Box::new(0.1, 0.05, 0.1))),
-                material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
-                transform: Transform::from_xyz(0.0, 0.1, 0.0),
+                mesh: meshes.add(geometry.shape.mesh()),
+                material: materials.add(Color::from(geometry.color).into()),
+                transform: transform_from(geometry.position, geometry.rotation),
                 ..default()
             },
-            Name::new("Left Hand"),
-        ))
-        .with_children(|parent| {
-            // grab point
-            parent.spawn((
-                SpatialBundle::from_transform(Transform::from_xyz(0.0, -0.05, 0.0)),
-                Grabber {
-                    hand: Hand::Left,
-                    search_radius: 0.1,
-                    grab_tolerance: 0.02,
-                    grabbable_layer_mask: Layer::Grabbable.to_bits(),
-                    state: GrabberState::Idle,
-                },
-                Name::new("Left Grab Point"),
-            ));
-        });
+            RigidBody::Static,
+            geometry.shape.collider(),
+            CollisionLayers::new([Layer::Default], [Layer::Default]),
+            Described,
+        ));
+        if let Some(name) = &geometry.name {
+            entity.insert(Name::new(name.clone()));
+        }
+    }
 
-    //right hand
-    let right_controller = commands
-        .spawn((
-            OpenXRRightController,
-            OpenXRController,
-            OpenXRTracker,
-            SpatialBundle::default(),
-            Name::new("Right Controller"),
-        ))
-        .id();
-    commands
-        .spawn((
-            VelocityTracked {
-                follow_target: right_controller,
-                follow_strength: 30.0,
-                max_distance: 0.75,
-                rotation_follow_strength: 30.0,
-            },
-            RigidBody::Dynamic,
-            ColliderDensity(1000.0),
-            Collider::cuboid(0.1, 0.05, 0.1),
-            CollisionLayers::new([Layer::Hand, Layer::Default], [Layer::Default]),
-            PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Box::new(0.1, 0.05, 0.1))),
-                material: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
-                transform: Transform::from_xyz(0.0, 0.1, 0.0),
+    for light in description.lights.iter() {
+        commands.spawn((
+            PointLightBundle {
+                point_light: PointLight {
+                    intensity: light.intensity,
+                    shadows_enabled: light.shadows,
+                    ..default()
+                },
+                transform: Transform::from_translation(light.position.into()),
                 ..default()
             },
-            Name::new("Right Hand"),
-        ))
-        .with_children(|parent| {
-            // grab point
-            parent.spawn((
-                SpatialBundle::from_transform(Transform::from_xyz(0.0, -0.05, 0.0)),
-                Grabber {
-                    hand: Hand::Right,
-                    search_radius: 0.1,
-                    grab_tolerance: 0.02,
-                    grabbable_layer_mask: Layer::Grabbable.to_bits(),
-                    state: GrabberState::Idle,
-                },
-                Name::new("Right Grab Point"),
-            ));
-        });
-}
-
-#[derive(Component, Reflect, Default, Debug)]
-#[reflect(Component)]
-struct Anchorable;
-
-#[derive(Component, Reflect, Default, Debug)]
-#[reflect(Component)]
-struct Anchor;
+            Described,
+        ));
+    }
 
-fn spawn_test_gltf(mut commands: Commands, asset_lib: AssetLib) {
-    commands.spawn(SceneBundle {
-        scene: asset_lib.scene("ship"),
-        transform: Transform::from_xyz(0.0, 0.25, 0.0),
-        ..default()
-    });
+    for kill_volume in description.kill_volumes.iter() {
+        commands.spawn((
+            SpatialBundle::from_transform(Transform::from_translation(kill_volume.position.into())),
+            KillVolume {
+                half_extents: Vec3::from(kill_volume.size) / 2.0,
+            },
+            Name::new("Kill Volume"),
+            Described,
+        ));
+    }
 
-    commands.spawn(HookedSceneBundle {
-        scene: SceneBundle {
-            scene: asset_lib.scene("ship"),
-            transform: Transform::from_xyz(0.0, 0.25, 0.0),
-            ..default()
-        },
-        hook: SceneHook::new(
-            |entity, cmds| match entity.get::<Name>().map(|t| t.as_str()) {
-                Some("Anchor") => {
-                    println!("Found anchor!");
-                    cmds.insert(Anchor);
-                }
-                Some(name) => println!("Found {}", name),
-                _ => println!("Found something unnamed"),
+    for zone in description.gravity_zones.iter() {
+        let mut entity = commands.spawn((
+            SpatialBundle::from_transform(transform_from(zone.position, zone.rotation)),
+            GravityZone {
+                shape: zone.shape.zone_shape(),
+                gravity: zone.gravity.zone_gravity(),
+                priority: zone.priority,
             },
-        ),
-    });
+            Described,
+        ));
+        if let Some(name) = &zone.name {
+            entity.insert(Name::new(name.clone()));
+        }
+    }
 
-    commands
-        .spawn((
-            SceneBundle {
-                scene: asset_lib.scene("hamster"),
-                transform: Transform::from_xyz(0.25, 0.25, 0.0),
-                ..default()
+    for prefab in description.prefabs.iter() {
+        prefab_registry.insert(
+            prefab.name.clone(),
+            Prefab {
+                mesh: meshes.add(prefab.shape.mesh()),
+                material: materials.add(Color::from(prefab.color).into()),
+                collider: prefab.shape.collider(),
+                density: prefab.density,
+                layers: CollisionLayers::new(
+                    prefab.layers.iter().copied(),
+                    prefab.collides_with.iter().copied(),
+                ),
+                transfer: prefab
+                    .transfer
+                    .map(|transfer| transfer.transfer_mode())
+                    .unwrap_or_default(),
+                out_of_bounds: prefab
+                    .out_of_bounds
+                    .map(|behavior| behavior.out_of_bounds_behavior())
+                    .unwrap_or_default(),
+                max_instances: prefab.max_instances,
+                gravity_scale: prefab.gravity_scale,
             },
-            RigidBody::Dynamic,
-            // CollisionLayers::new([Layer::Grabbable, Layer::Default], [Layer::Default]),
-            Grabbable { grabbed_by: vec![] },
-        ))
-        .with_children(|parent| {
-            parent.spawn((
-                SpatialBundle::from_transform(Transform::from_xyz(0.0, 0.05, 0.0)),
-                ColliderDensity(1000.0),
-                Collider::cuboid(0.05, 0.1, 0.05),
-                CollisionLayers::new([Layer::Grabbable, Layer::Default], [Layer::Default]),
-                Name::new("Collider"),
-            ));
-        });
+        );
+    }
 
-    commands.spawn((
-        SceneBundle {
-            scene: asset_lib.scene("asteroid"),
-            transform: Transform::from_xyz(0.0, 0.25, 0.25),
-            ..default()
-        },
-        RigidBody::Dynamic,
-        Grabbable { grabbed_by: vec![] },
-        ColliderDensity(4000.0),
-        Collider::ball(0.16),
-        CollisionLayers::new([Layer::Grabbable, Layer::Default], [Layer::Default]),
-    ));
-}
+    for grabbable in description.grabbables.iter() {
+        let mut entity = commands.spawn_prefab(
+            grabbable.prefab.clone(),
+            transform_from(grabbable.position, grabbable.rotation),
+        );
+        entity.insert(Described);
+        if let Some(name) = &grabbable.name {
+            entity.insert(Name::new(name.clone()));
+        }
+    }
 
-fn log_if_anchorable_found(anchorables: Query<&Anchorable>) {
-    if anchorables.is_empty() {
-        println!("NO anchorables!");
-    } else {
-        println!("Found anchorables!");
+    // Only a different rig respawns the hands, not every edit to the scene
+    if let Some(player) = &description.player {
+        rig_config.set_if_neq(player.rig_config());
     }
 }
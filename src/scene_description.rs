@@ -0,0 +1,447 @@
+//! Scenes described in `.scene.ron` files, so the layout and the player rig's tunables can be
+//! changed without recompiling. With the `hot_reload` feature, edits are picked up while running.
+//!
+//! Hot reloading only works for assets read from the filesystem, like on desktop. On Android the
+//! assets are packed into the APK, which can't change while the app runs, so there's nothing to
+//! watch: reinstall the APK to pick up edits.
+
+use std::fmt;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use bevy_xpbd_3d::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    bounds::OutOfBoundsBehavior,
+    gravity::{ZoneGravity, ZoneShape},
+    vr_hands::{
+        grabber::TransferMode,
+        rig::{HandConfig, HandShape, RigConfig},
+        velocity_tracking::VelocityTracked,
+    },
+    Layer,
+};
+
+pub struct SceneDescriptionPlugin;
+
+impl Plugin for SceneDescriptionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<SceneDescription>()
+            .init_asset_loader::<SceneDescriptionLoader>();
+    }
+}
+
+#[derive(Asset, TypePath, Deserialize, Debug, Clone)]
+pub struct SceneDescription {
+    /// Replaces the global gravity, which applies everywhere outside the gravity zones.
+    #[serde(default)]
+    pub gravity: Option<[f32; 3]>,
+    #[serde(default)]
+    pub static_geometry: Vec<StaticGeometry>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    #[serde(default)]
+    pub kill_volumes: Vec<KillVolumeDescription>,
+    #[serde(default)]
+    pub gravity_zones: Vec<GravityZoneDescription>,
+    /// Prefabs to add to the [`PrefabRegistry`](crate::prefabs::PrefabRegistry), on top of the
+    /// built in ones.
+    #[serde(default)]
+    pub prefabs: Vec<PrefabDescription>,
+    #[serde(default)]
+    pub grabbables: Vec<GrabbableDescription>,
+    /// Replaces the player rig's config, if the scene should change the hands.
+    #[serde(default)]
+    pub player: Option<PlayerRigDescription>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum ShapeDescription {
+    /// A box with the given full extents.
+    Cuboid {
+        size: [f32; 3],
+    },
+    Sphere {
+        radius: f32,
+    },
+    /// A square of the given size to look at, colliding as an infinite ground plane.
+    Plane {
+        size: f32,
+    },
+}
+
+impl ShapeDescription {
+    pub fn mesh(&self) -> Mesh {
+        match *self {
+            ShapeDescription::Cuboid { size: [x, y, z] } => shape::Box::new(x, y, z).into(),
+            ShapeDescription::Sphere { radius } => shape::UVSphere {
+                radius,
+                ..default()
+            }
+            .into(),
+            ShapeDescription::Plane { size } => shape::Plane::from_size(size).into(),
+        }
+    }
+
+    pub fn collider(&self) -> Collider {
+        match *self {
+            ShapeDescription::Cuboid { size: [x, y, z] } => Collider::cuboid(x, y, z),
+            ShapeDescription::Sphere { radius } => Collider::ball(radius),
+            ShapeDescription::Plane { .. } => Collider::halfspace(Vec3::Y),
+        }
+    }
+}
+
+/// A static body, like the ground or walls.
+#[derive(Deserialize, Debug, Clone)]
+pub struct StaticGeometry {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub shape: ShapeDescription,
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// Euler angles in degrees, applied in XYZ order.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "default_color")]
+    pub color: [f32; 3],
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LightDescription {
+    #[serde(default)]
+    pub position: [f32; 3],
+    pub intensity: f32,
+    #[serde(default)]
+    pub shadows: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PrefabDescription {
+    pub name: String,
+    pub shape: ShapeDescription,
+    #[serde(default = "default_color")]
+    pub color: [f32; 3],
+    #[serde(default = "default_density")]
+    pub density: f32,
+    /// The collision layers the object is on.
+    #[serde(default = "default_grabbable_layers")]
+    pub layers: Vec<Layer>,
+    /// The collision layers the object collides with.
+    #[serde(default = "default_collides_with")]
+    pub collides_with: Vec<Layer>,
+    /// The [`TransferMode`], if not the default.
+    #[serde(default)]
+    pub transfer: Option<TransferModeDescription>,
+    /// The [`OutOfBoundsBehavior`], if not the default.
+    #[serde(default)]
+    pub out_of_bounds: Option<OutOfBoundsDescription>,
+    #[serde(default)]
+    pub max_instances: Option<usize>,
+    /// Scales the gravity the object feels.
+    #[serde(default = "default_gravity_scale")]
+    pub gravity_scale: f32,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum TransferModeDescription {
+    Steal,
+    TwoHanded,
+    Refuse,
+}
+
+impl TransferModeDescription {
+    pub fn transfer_mode(&self) -> TransferMode {
+        match *self {
+            TransferModeDescription::Steal => TransferMode::Steal,
+            TransferModeDescription::TwoHanded => TransferMode::TwoHanded,
+            TransferModeDescription::Refuse => TransferMode::Refuse,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum OutOfBoundsDescription {
+    Despawn,
+    RespawnAtOrigin,
+    ReturnToLastSocket,
+}
+
+impl OutOfBoundsDescription {
+    pub fn out_of_bounds_behavior(&self) -> OutOfBoundsBehavior {
+        match *self {
+            OutOfBoundsDescription::Despawn => OutOfBoundsBehavior::Despawn,
+            OutOfBoundsDescription::RespawnAtOrigin => OutOfBoundsBehavior::RespawnAtOrigin,
+            OutOfBoundsDescription::ReturnToLastSocket => OutOfBoundsBehavior::ReturnToLastSocket,
+        }
+    }
+}
+
+/// A box that grabbables are out of bounds in.
+#[derive(Deserialize, Debug, Clone)]
+pub struct KillVolumeDescription {
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// The box's full extents.
+    pub size: [f32; 3],
+}
+
+/// A volume with its own gravity.
+#[derive(Deserialize, Debug, Clone)]
+pub struct GravityZoneDescription {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub shape: ZoneShapeDescription,
+    pub gravity: ZoneGravityDescription,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// Euler angles in degrees, applied in XYZ order.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum ZoneShapeDescription {
+    /// A box with the given full extents.
+    Cuboid {
+        size: [f32; 3],
+    },
+    Sphere {
+        radius: f32,
+    },
+}
+
+impl ZoneShapeDescription {
+    pub fn zone_shape(&self) -> ZoneShape {
+        match *self {
+            ZoneShapeDescription::Cuboid { size } => ZoneShape::Box {
+                half_extents: Vec3::from(size) / 2.0,
+            },
+            ZoneShapeDescription::Sphere { radius } => ZoneShape::Sphere { radius },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum ZoneGravityDescription {
+    Zero,
+    /// A constant acceleration, in the zone's frame.
+    Directional {
+        acceleration: [f32; 3],
+    },
+    /// An acceleration of this magnitude towards the zone's center.
+    Radial {
+        acceleration: f32,
+    },
+}
+
+impl ZoneGravityDescription {
+    pub fn zone_gravity(&self) -> ZoneGravity {
+        match *self {
+            ZoneGravityDescription::Zero => ZoneGravity::Zero,
+            ZoneGravityDescription::Directional { acceleration } => {
+                ZoneGravity::Directional(acceleration.into())
+            }
+            ZoneGravityDescription::Radial { acceleration } => ZoneGravity::Radial(acceleration),
+        }
+    }
+}
+
+/// A prefab placed in the scene.
+#[derive(Deserialize, Debug, Clone)]
+pub struct GrabbableDescription {
+    pub prefab: String,
+    /// Overrides the prefab's name.
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// Euler angles in degrees, applied in XYZ order.
+    #[serde(default)]
+    pub rotation: [f32; 3],
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PlayerRigDescription {
+    /// Both hands are built from the same description.
+    #[serde(default)]
+    pub hand: HandDescription,
+    #[serde(default = "default_true")]
+    pub left: bool,
+    #[serde(default = "default_true")]
+    pub right: bool,
+}
+
+impl Default for PlayerRigDescription {
+    fn default() -> Self {
+        Self {
+            hand: HandDescription::default(),
+            left: true,
+            right: true,
+        }
+    }
+}
+
+impl PlayerRigDescription {
+    pub fn rig_config(&self) -> RigConfig {
+        let hand = self.hand.hand_config();
+        RigConfig {
+            left: self.left.then(|| hand.clone()),
+            right: self.right.then_some(hand),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct HandDescription {
+    pub size: [f32; 3],
+    pub color: [f32; 3],
+    pub density: f32,
+    pub follow_strength: f32,
+    pub rotation_follow_strength: f32,
+    pub max_distance: f32,
+    /// Where the grab point is relative to the hand.
+    pub grab_point: [f32; 3],
+    pub search_radius: f32,
+    pub grab_tolerance: f32,
+}
+
+// The same hand as the rig builds without a description
+impl Default for HandDescription {
+    fn default() -> Self {
+        let config = HandConfig::default();
+        let HandShape::Box(size) = config.shape else {
+            unreachable!("the default hand is a box");
+        };
+        let [r, g, b, _] = config.color.as_rgba_f32();
+        Self {
+            size: size.into(),
+            color: [r, g, b],
+            density: config.density,
+            follow_strength: config.tracking.follow_strength,
+            rotation_follow_strength: config.tracking.rotation_follow_strength,
+            max_distance: config.tracking.max_distance,
+            grab_point: config.grab_point.into(),
+            search_radius: config.search_radius,
+            grab_tolerance: config.grab_tolerance,
+        }
+    }
+}
+
+impl HandDescription {
+    pub fn hand_config(&self) -> HandConfig {
+        let defaults = HandConfig::default();
+        HandConfig {
+            shape: HandShape::Box(self.size.into()),
+            color: Color::from(self.color),
+            density: self.density,
+            tracking: VelocityTracked {
+                follow_strength: self.follow_strength,
+                rotation_follow_strength: self.rotation_follow_strength,
+                max_distance: self.max_distance,
+                ..defaults.tracking
+            },
+            grab_point: self.grab_point.into(),
+            search_radius: self.search_radius,
+            grab_tolerance: self.grab_tolerance,
+            ..defaults
+        }
+    }
+}
+
+/// Converts a described position and rotation into a transform.
+pub fn transform_from(position: [f32; 3], rotation: [f32; 3]) -> Transform {
+    let [x, y, z] = rotation.map(f32::to_radians);
+    Transform::from_translation(position.into()).with_rotation(Quat::from_euler(
+        EulerRot::XYZ,
+        x,
+        y,
+        z,
+    ))
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_color() -> [f32; 3] {
+    [0.8, 0.8, 0.8]
+}
+
+fn default_density() -> f32 {
+    1000.0
+}
+
+fn default_gravity_scale() -> f32 {
+    1.0
+}
+
+fn default_grabbable_layers() -> Vec<Layer> {
+    vec![Layer::Grabbable, Layer::Default]
+}
+
+fn default_collides_with() -> Vec<Layer> {
+    vec![Layer::Default]
+}
+
+#[derive(Default)]
+pub struct SceneDescriptionLoader;
+
+#[derive(Debug)]
+pub enum SceneDescriptionLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl fmt::Display for SceneDescriptionLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneDescriptionLoaderError::Io(error) => write!(f, "could not read scene: {error}"),
+            SceneDescriptionLoaderError::Ron(error) => write!(f, "could not parse scene: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneDescriptionLoaderError {}
+
+impl From<std::io::Error> for SceneDescriptionLoaderError {
+    fn from(error: std::io::Error) -> Self {
+        SceneDescriptionLoaderError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for SceneDescriptionLoaderError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        SceneDescriptionLoaderError::Ron(error)
+    }
+}
+
+impl AssetLoader for SceneDescriptionLoader {
+    type Asset = SceneDescription;
+    type Settings = ();
+    type Error = SceneDescriptionLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<SceneDescription, SceneDescriptionLoaderError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["scene.ron"]
+    }
+}
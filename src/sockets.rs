@@ -0,0 +1,382 @@
+use bevy::{
+    ecs::system::{EntityCommands, SystemState},
+    prelude::*,
+};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::{
+    body_of,
+    vr_hands::{
+        fixed_joint_2::FixedJoint2,
+        grabber::{held_bodies, GrabReleasedEvent, Grabber},
+    },
+};
+
+pub struct SocketPlugin;
+
+impl Plugin for SocketPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Anchorable>()
+            .register_type::<Anchor>()
+            .add_systems(Startup, setup_ghost_material)
+            .add_systems(
+                Update,
+                (
+                    add_static_body_to_free_anchors,
+                    detach_grabbed_anchorables,
+                    snap_released_anchorables,
+                    update_socket_ghosts,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// A socket that a released [`Anchorable`] snaps into, matching the anchor's position and orientation.
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
+pub struct Anchor {
+    /// Tags of the anchorables this anchor accepts. An empty list accepts any anchorable.
+    pub accepts: Vec<String>,
+    /// How close an anchorable has to be released to snap into this anchor.
+    pub snap_radius: f32,
+    /// The anchorable currently snapped into this anchor.
+    pub occupant: Option<Entity>,
+}
+
+impl Default for Anchor {
+    fn default() -> Self {
+        Self {
+            accepts: vec![],
+            snap_radius: 0.1,
+            occupant: None,
+        }
+    }
+}
+
+impl Anchor {
+    pub fn accepts(&self, anchorable: &Anchorable) -> bool {
+        self.accepts.is_empty() || self.accepts.contains(&anchorable.tag)
+    }
+}
+
+/// An attachment point on a grabbable object that can be snapped into an [`Anchor`].
+#[derive(Component, Reflect, Default, Debug, Clone)]
+#[reflect(Component)]
+pub struct Anchorable {
+    /// Compatibility tag, matched against [`Anchor::accepts`].
+    pub tag: String,
+}
+
+/// Added to an [`Anchorable`] while it is snapped into an anchor.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Socketed {
+    pub anchor: Entity,
+    pub joint: Entity,
+}
+
+#[derive(Component)]
+struct SocketGhost {
+    anchorable: Entity,
+}
+
+#[derive(Resource)]
+struct GhostMaterial(Handle<StandardMaterial>);
+
+fn setup_ghost_material(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    let material = materials.add(StandardMaterial {
+        base_color: Color::rgba(0.3, 0.8, 1.0, 0.35),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    });
+    commands.insert_resource(GhostMaterial(material));
+}
+
+// Joints need a body on both ends, so anchors that aren't part of a rigid body get a static one.
+fn add_static_body_to_free_anchors(
+    mut commands: Commands,
+    anchors: Query<Entity, Added<Anchor>>,
+    rbs: Query<(), With<RigidBody>>,
+    parents: Query<&Parent>,
+) {
+    for anchor in anchors.iter() {
+        if body_of(anchor, &rbs, &parents).is_none() {
+            commands.entity(anchor).insert(RigidBody::Static);
+        }
+    }
+}
+
+/// The joint holding an anchorable's body in an anchor's body, given the anchor and the anchorable
+/// relative to their bodies.
+pub fn socket_joint(
+    anchor_body: Entity,
+    anchor_local: Transform,
+    anchorable_body: Entity,
+    anchorable_local: Transform,
+) -> FixedJoint2 {
+    // anchor_body_rotation * anchor_local.rotation = anchorable_body_rotation * anchorable_local.rotation
+    let rotation_offset = anchor_local.rotation * anchorable_local.rotation.inverse();
+    FixedJoint2::new(anchor_body, anchorable_body)
+        .with_local_anchor_1(anchor_local.translation)
+        .with_local_anchor_2(anchorable_local.translation)
+        .with_rotation_offset(rotation_offset.into())
+}
+
+/// Snaps `anchorable` into `anchor` as they are now, with a new socket joint. Returns false if the
+/// anchor holds something else, or either of them isn't part of a body.
+#[allow(clippy::type_complexity)]
+pub fn socket_into(world: &mut World, anchorable: Entity, anchor: Entity) -> bool {
+    let mut state: SystemState<(
+        Query<&Anchor>,
+        Query<&GlobalTransform>,
+        Query<(), With<RigidBody>>,
+        Query<&Parent>,
+    )> = SystemState::new(world);
+    let (anchors, transforms, rbs, parents) = state.get(world);
+
+    let Ok(anchor_component) = anchors.get(anchor) else {
+        return false;
+    };
+    if anchor_component
+        .occupant
+        .is_some_and(|occupant| occupant != anchorable)
+    {
+        return false;
+    }
+    let (Some(anchor_body), Some(anchorable_body)) = (
+        body_of(anchor, &rbs, &parents),
+        body_of(anchorable, &rbs, &parents),
+    ) else {
+        return false;
+    };
+    let (Ok(anchor_transform), Ok(anchor_body_transform)) =
+        (transforms.get(anchor), transforms.get(anchor_body))
+    else {
+        return false;
+    };
+    let (Ok(anchorable_transform), Ok(anchorable_body_transform)) =
+        (transforms.get(anchorable), transforms.get(anchorable_body))
+    else {
+        return false;
+    };
+    // Relative to their bodies, so this holds wherever the bodies have been moved to since
+    let joint = socket_joint(
+        anchor_body,
+        anchor_transform.reparented_to(anchor_body_transform),
+        anchorable_body,
+        anchorable_transform.reparented_to(anchorable_body_transform),
+    );
+
+    let joint = world.spawn((joint, Name::new("Socket Joint"))).id();
+    world
+        .entity_mut(anchorable)
+        .insert(Socketed { anchor, joint });
+    if let Some(mut anchor) = world.get_mut::<Anchor>(anchor) {
+        anchor.occupant = Some(anchorable);
+    }
+    true
+}
+
+/// A [`SceneHook`](bevy_scene_hook::SceneHook) that makes an anchor of every glTF node named
+/// "Anchor".
+pub fn add_anchors(entity: &EntityRef, cmds: &mut EntityCommands) {
+    if entity
+        .get::<Name>()
+        .is_some_and(|name| name.as_str() == "Anchor")
+    {
+        cmds.insert(Anchor::default());
+    }
+}
+
+/// Finds the closest free anchor that accepts `anchorable` and is within its snap radius.
+fn closest_free_anchor<'a>(
+    anchorable: &Anchorable,
+    position: Vec3,
+    anchors: impl Iterator<Item = (Entity, &'a Anchor, &'a GlobalTransform)>,
+) -> Option<(Entity, GlobalTransform)> {
+    anchors
+        .filter(|(_, anchor, _)| anchor.occupant.is_none() && anchor.accepts(anchorable))
+        .map(|(entity, anchor, transform)| {
+            let distance = transform.translation().distance(position);
+            (entity, anchor, transform, distance)
+        })
+        .filter(|(_, anchor, _, distance)| *distance <= anchor.snap_radius)
+        .min_by(|(_, _, _, d1), (_, _, _, d2)| d1.partial_cmp(d2).unwrap())
+        .map(|(entity, _, transform, _)| (entity, *transform))
+}
+
+fn detach_grabbed_anchorables(
+    mut commands: Commands,
+    grabbers: Query<&Grabber>,
+    socketed: Query<(Entity, &Socketed)>,
+    mut anchors: Query<&mut Anchor>,
+    rbs: Query<(), With<RigidBody>>,
+    parents: Query<&Parent>,
+) {
+    let held = held_bodies(&grabbers);
+    for (anchorable, socketed) in socketed.iter() {
+        let Some(body) = body_of(anchorable, &rbs, &parents) else {
+            continue;
+        };
+        if !held.contains(&body) {
+            continue;
+        }
+
+        debug!("Detaching {:?} from {:?}", anchorable, socketed.anchor);
+        commands.entity(socketed.joint).despawn_recursive();
+        commands.entity(anchorable).remove::<Socketed>();
+        if let Ok(mut anchor) = anchors.get_mut(socketed.anchor) {
+            anchor.occupant = None;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn snap_released_anchorables(
+    mut commands: Commands,
+    mut released_events: EventReader<GrabReleasedEvent>,
+    grabbers: Query<&Grabber>,
+    anchorables: Query<(Entity, &Anchorable, &GlobalTransform), Without<Socketed>>,
+    mut anchors: Query<(Entity, &mut Anchor, &GlobalTransform)>,
+    transforms: Query<&GlobalTransform>,
+    rbs: Query<(), With<RigidBody>>,
+    parents: Query<&Parent>,
+) {
+    let held = held_bodies(&grabbers);
+    for event in released_events.read() {
+        // Still held by the other hand
+        if held.contains(&event.grabbed) {
+            continue;
+        }
+
+        for (anchorable_entity, anchorable, anchorable_transform) in anchorables.iter() {
+            let Some(anchorable_body) = body_of(anchorable_entity, &rbs, &parents) else {
+                continue;
+            };
+            if anchorable_body != event.grabbed {
+                continue;
+            }
+
+            let Some((anchor_entity, anchor_transform)) = closest_free_anchor(
+                anchorable,
+                anchorable_transform.translation(),
+                anchors.iter(),
+            ) else {
+                continue;
+            };
+            let Some(anchor_body) = body_of(anchor_entity, &rbs, &parents) else {
+                continue;
+            };
+
+            // Express both attachment frames relative to the bodies the joint connects
+            let anchor_local = anchor_transform.reparented_to(transforms.get(anchor_body).unwrap());
+            let anchorable_local =
+                anchorable_transform.reparented_to(transforms.get(anchorable_body).unwrap());
+
+            let joint = commands
+                .spawn((
+                    socket_joint(anchor_body, anchor_local, anchorable_body, anchorable_local),
+                    Name::new("Socket Joint"),
+                ))
+                .id();
+
+            debug!("Snapping {:?} into {:?}", anchorable_entity, anchor_entity);
+            commands.entity(anchorable_entity).insert(Socketed {
+                anchor: anchor_entity,
+                joint,
+            });
+            let (_, mut anchor, _) = anchors.get_mut(anchor_entity).unwrap();
+            anchor.occupant = Some(anchorable_entity);
+        }
+    }
+}
+
+// Show a translucent copy of each held anchorable's body where it would snap to if released
+#[allow(clippy::too_many_arguments)]
+fn update_socket_ghosts(
+    mut commands: Commands,
+    grabbers: Query<&Grabber>,
+    anchorables: Query<(Entity, &Anchorable, &GlobalTransform), Without<Socketed>>,
+    anchors: Query<(Entity, &Anchor, &GlobalTransform)>,
+    mut ghosts: Query<(Entity, &SocketGhost, &mut Transform)>,
+    transforms: Query<&GlobalTransform>,
+    meshes: Query<&Handle<Mesh>>,
+    children: Query<&Children>,
+    rbs: Query<(), With<RigidBody>>,
+    parents: Query<&Parent>,
+    ghost_material: Res<GhostMaterial>,
+    mut gizmos: Gizmos,
+) {
+    let held = held_bodies(&grabbers);
+    let mut hovering = vec![];
+
+    for (anchorable_entity, anchorable, anchorable_transform) in anchorables.iter() {
+        let Some(body) = body_of(anchorable_entity, &rbs, &parents) else {
+            continue;
+        };
+        if !held.contains(&body) {
+            continue;
+        }
+        let Some((_, anchor_transform)) = closest_free_anchor(
+            anchorable,
+            anchorable_transform.translation(),
+            anchors.iter(),
+        ) else {
+            continue;
+        };
+
+        gizmos.line(
+            anchorable_transform.translation(),
+            anchor_transform.translation(),
+            Color::rgb(0.3, 0.8, 1.0),
+        );
+
+        // The body pose that puts the anchorable exactly on the anchor
+        let body_transform = transforms.get(body).unwrap();
+        let anchorable_local = anchorable_transform.reparented_to(body_transform);
+        let target = anchor_transform.mul_transform(Transform::from_matrix(
+            anchorable_local.compute_matrix().inverse(),
+        ));
+        let target = target.compute_transform();
+        hovering.push(anchorable_entity);
+
+        if let Some((_, _, mut ghost_transform)) = ghosts
+            .iter_mut()
+            .find(|(_, ghost, _)| ghost.anchorable == anchorable_entity)
+        {
+            *ghost_transform = target;
+            continue;
+        }
+
+        commands
+            .spawn((
+                SocketGhost {
+                    anchorable: anchorable_entity,
+                },
+                SpatialBundle::from_transform(target),
+                Name::new("Socket Ghost"),
+            ))
+            .with_children(|parent| {
+                for part in std::iter::once(body).chain(children.iter_descendants(body)) {
+                    let Ok(mesh) = meshes.get(part) else {
+                        continue;
+                    };
+                    let part_transform =
+                        transforms.get(part).unwrap().reparented_to(body_transform);
+                    parent.spawn(PbrBundle {
+                        mesh: mesh.clone(),
+                        material: ghost_material.0.clone(),
+                        transform: part_transform,
+                        ..default()
+                    });
+                }
+            });
+    }
+
+    for (ghost_entity, ghost, _) in ghosts.iter() {
+        if !hovering.contains(&ghost.anchorable) {
+            commands.entity(ghost_entity).despawn_recursive();
+        }
+    }
+}
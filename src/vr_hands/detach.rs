@@ -0,0 +1,90 @@
+//! Letting go of a body properly before it's despawned or moved: the joints attached to it and any
+//! grabs on it or by it.
+
+use bevy::{
+    ecs::system::{Command, SystemState},
+    hierarchy::despawn_with_children_recursive,
+    prelude::*,
+    utils::HashSet,
+};
+
+use super::{
+    fixed_joint_2::FixedJoint2,
+    generic_joint::GenericJoint,
+    grabber::{Grabbable, Grabber, GrabberState},
+};
+
+/// Despawns a body with its children, along with its joints and grabs.
+pub struct DespawnBody {
+    pub body: Entity,
+}
+
+impl Command for DespawnBody {
+    fn apply(self, world: &mut World) {
+        if world.get_entity(self.body).is_none() {
+            return;
+        }
+        detach_body(world, self.body);
+        despawn_with_children_recursive(world, self.body);
+    }
+}
+
+/// A body and all of its descendants.
+pub fn parts_of(world: &mut World, body: Entity) -> HashSet<Entity> {
+    let mut state: SystemState<Query<&Children>> = SystemState::new(world);
+    let children = state.get(world);
+    std::iter::once(body)
+        .chain(children.iter_descendants(body))
+        .collect()
+}
+
+/// Lets go of a body and everything in it: despawns the joints attached to any of its parts, and
+/// ends grabs on them. Hands being detached let go of what they hold the same way.
+#[allow(clippy::type_complexity)]
+pub fn detach_body(world: &mut World, body: Entity) {
+    let parts = parts_of(world, body);
+    let mut state: SystemState<(
+        Commands,
+        Query<(Entity, &FixedJoint2)>,
+        Query<(Entity, &GenericJoint)>,
+        Query<&mut Grabber>,
+        Query<&mut Grabbable>,
+    )> = SystemState::new(world);
+    let (mut commands, fixed_joints, generic_joints, mut grabbers, mut grabbables) =
+        state.get_mut(world);
+
+    let joints =
+        fixed_joints
+            .iter()
+            .map(|(joint, fixed_joint)| (joint, [fixed_joint.entity1, fixed_joint.entity2]))
+            .chain(generic_joints.iter().map(|(joint, generic_joint)| {
+                (joint, [generic_joint.entity1, generic_joint.entity2])
+            }));
+    for (joint, bodies) in joints {
+        if bodies.iter().any(|body| parts.contains(body)) {
+            commands.entity(joint).despawn_recursive();
+        }
+    }
+
+    for mut grabber in grabbers.iter_mut() {
+        grabber.state = match grabber.state {
+            GrabberState::Grabbed(held, _) if parts.contains(&held) => GrabberState::Idle,
+            GrabberState::Grabbing(Some((target, _))) if parts.contains(&target) => {
+                GrabberState::Grabbing(None)
+            }
+            state => state,
+        };
+    }
+    for mut grabbable in grabbables.iter_mut() {
+        grabbable
+            .grabbed_by
+            .retain(|grabber| !parts.contains(grabber));
+    }
+    for part in parts.iter() {
+        if let Ok(mut grabbable) = grabbables.get_mut(*part) {
+            grabbable.grabbed_by.clear();
+        }
+    }
+
+    state.apply(world);
+}
@@ -2,7 +2,10 @@
 //! Copied from bevy_xpbd FixedJoint to add rotation offset support.
 
 use bevy::{
-    ecs::entity::{EntityMapper, MapEntities},
+    ecs::{
+        entity::{EntityMapper, MapEntities},
+        reflect::ReflectMapEntities,
+    },
     prelude::*,
 };
 use bevy_xpbd_3d::{
@@ -16,6 +19,10 @@ pub struct FixedJoint2Plugin;
 
 impl Plugin for FixedJoint2Plugin {
     fn build(&self, app: &mut App) {
+        app.register_type::<FixedJoint2>()
+            .register_type::<JointSpring>()
+            .register_type::<Option<JointSpring>>();
+
         let substeps = app
             .get_schedule_mut(SubstepSchedule)
             .expect("add SubstepSchedule first");
@@ -40,7 +47,8 @@ impl Plugin for FixedJoint2Plugin {
 ///
 /// You should generally prefer using a single body instead of multiple bodies fixed together,
 /// but fixed joints can be useful for things like rigid structures where a force can dynamically break the joints connecting individual bodies.
-#[derive(Component, Clone, Copy, Debug, PartialEq)]
+#[derive(Component, Reflect, Clone, Copy, Debug, PartialEq)]
+#[reflect(Component, MapEntities)]
 #[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct FixedJoint2 {
     /// First entity constrained by the joint.
@@ -61,14 +69,52 @@ pub struct FixedJoint2 {
     pub position_lagrange: Scalar,
     /// Lagrange multiplier for the angular correction caused by the alignment of the bodies.
     pub align_lagrange: Scalar,
-    /// The joint's compliance, the inverse of stiffness, has the unit meters / Newton.
-    pub compliance: Scalar,
+    /// Compliance of the position constraint, the inverse of stiffness, has the unit meters / Newton.
+    pub linear_compliance: Scalar,
+    /// Compliance of the orientation constraint, has the unit radians / Newton meter.
+    pub angular_compliance: Scalar,
+    /// Drive the position constraint like a damped spring instead, overriding `linear_compliance`.
+    pub linear_spring: Option<JointSpring>,
+    /// Drive the orientation constraint like a damped spring instead, overriding `angular_compliance`.
+    pub angular_spring: Option<JointSpring>,
     /// The force exerted by the joint.
     pub force: Vector,
     /// The torque exerted by the joint when aligning the bodies.
     pub align_torque: Vector,
 }
 
+/// A spring-damper parameterization of a joint constraint, independent of the masses involved.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct JointSpring {
+    /// How fast the spring oscillates, in Hz.
+    pub frequency: Scalar,
+    /// 1 is critically damped, lower values overshoot and higher values approach slower.
+    pub damping_ratio: Scalar,
+}
+
+impl JointSpring {
+    pub fn new(frequency: Scalar, damping_ratio: Scalar) -> Self {
+        Self {
+            frequency,
+            damping_ratio,
+        }
+    }
+
+    /// The compliance that behaves like this spring for a constraint with generalized inverse
+    /// mass `inverse_mass`, solved with substep `dt`.
+    pub fn compliance(&self, inverse_mass: Scalar, dt: Scalar) -> Scalar {
+        let omega = 2.0 * std::f32::consts::PI * self.frequency;
+        // stiffness = m * omega^2 and damping = 2 * m * zeta * omega, with the damping folded
+        // into the stiffness over one substep
+        let stiffness_per_mass = omega * omega + 2.0 * self.damping_ratio * omega / dt;
+        if stiffness_per_mass <= 0.0 {
+            return 0.0;
+        }
+        inverse_mass / stiffness_per_mass
+    }
+}
+
 impl XpbdConstraint<2> for FixedJoint2 {
     fn entities(&self) -> [Entity; 2] {
         [self.entity1, self.entity2]
@@ -81,14 +127,36 @@ impl XpbdConstraint<2> for FixedJoint2 {
 
     fn solve(&mut self, bodies: [&mut RigidBodyQueryItem; 2], dt: Scalar) {
         let [body1, body2] = bodies;
-        let compliance = self.compliance;
 
         // Align orientation
         let dq = self.get_delta_q(&body1.rotation, &body2.rotation);
+        let compliance = match self.angular_spring {
+            Some(spring) => {
+                let axis = dq.try_normalize().unwrap_or(Vector::Y);
+                let inverse_mass =
+                    AngularConstraint::compute_generalized_inverse_mass(self, body1, axis)
+                        + AngularConstraint::compute_generalized_inverse_mass(self, body2, axis);
+                spring.compliance(inverse_mass, dt)
+            }
+            None => self.angular_compliance,
+        };
         let mut lagrange = self.align_lagrange;
         self.align_torque = self.align_orientation(body1, body2, dq, &mut lagrange, compliance, dt);
         self.align_lagrange = lagrange;
 
+        let compliance = match self.linear_spring {
+            Some(spring) => {
+                // Static and kinematic bodies act as if they had infinite mass
+                let inverse_mass = [&*body1, &*body2]
+                    .iter()
+                    .filter(|body| body.rb.is_dynamic())
+                    .map(|body| body.inverse_mass.0)
+                    .sum();
+                spring.compliance(inverse_mass, dt)
+            }
+            None => self.linear_compliance,
+        };
+
         // Align position of local attachment points
         let mut lagrange = self.position_lagrange;
         self.force = self.align_position(
@@ -116,14 +184,21 @@ impl Joint for FixedJoint2 {
             damping_angular: 1.0,
             position_lagrange: 0.0,
             align_lagrange: 0.0,
-            compliance: 0.0,
+            linear_compliance: 0.0,
+            angular_compliance: 0.0,
+            linear_spring: None,
+            angular_spring: None,
             force: Vector::ZERO,
             align_torque: Vector::ZERO,
         }
     }
 
     fn with_compliance(self, compliance: Scalar) -> Self {
-        Self { compliance, ..self }
+        Self {
+            linear_compliance: compliance,
+            angular_compliance: compliance,
+            ..self
+        }
     }
 
     fn with_local_anchor_1(self, anchor: Vector) -> Self {
@@ -171,6 +246,13 @@ impl Joint for FixedJoint2 {
     }
 }
 
+// Needed to insert the joint from a scene, where the entities get mapped after insertion
+impl Default for FixedJoint2 {
+    fn default() -> Self {
+        Self::new(Entity::PLACEHOLDER, Entity::PLACEHOLDER)
+    }
+}
+
 impl FixedJoint2 {
     pub fn with_rotation_offset(self, offset: Rotation) -> Self {
         let offset = offset.normalize();
@@ -181,6 +263,34 @@ impl FixedJoint2 {
         }
     }
 
+    pub fn with_linear_compliance(self, compliance: Scalar) -> Self {
+        Self {
+            linear_compliance: compliance,
+            ..self
+        }
+    }
+
+    pub fn with_angular_compliance(self, compliance: Scalar) -> Self {
+        Self {
+            angular_compliance: compliance,
+            ..self
+        }
+    }
+
+    pub fn with_linear_spring(self, spring: JointSpring) -> Self {
+        Self {
+            linear_spring: Some(spring),
+            ..self
+        }
+    }
+
+    pub fn with_angular_spring(self, spring: JointSpring) -> Self {
+        Self {
+            angular_spring: Some(spring),
+            ..self
+        }
+    }
+
     fn get_delta_q(&self, rot1: &Rotation, rot2: &Rotation) -> Vector {
         let delta_q = rot1.0 * self.rotation_offset.0 * rot2.inverse().0;
         let delta_q = if delta_q.w < 0.0 { delta_q } else { -delta_q };
@@ -0,0 +1,622 @@
+//! [`GenericJoint`] component.
+//! A configurable joint with a mode per translational and rotational axis, so sliders, hinges,
+//! ball sockets and grabs don't each need a joint of their own.
+
+use bevy::{
+    ecs::{
+        entity::{EntityMapper, MapEntities},
+        reflect::ReflectMapEntities,
+    },
+    prelude::*,
+};
+use bevy_xpbd_3d::{
+    math::{Scalar, Vector},
+    plugins::solver::joint_damping,
+    prelude::*,
+    SubstepSchedule, SubstepSet,
+};
+
+use super::fixed_joint_2::FixedJoint2;
+
+pub struct GenericJointPlugin;
+
+impl Plugin for GenericJointPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<GenericJoint>()
+            .register_type::<AxisMode>()
+            .register_type::<[AxisMode; 3]>()
+            .register_type::<JointMotor>()
+            .register_type::<Option<JointMotor>>()
+            .register_type::<[Option<JointMotor>; 3]>()
+            .register_type::<JointAxis>();
+
+        let substeps = app
+            .get_schedule_mut(SubstepSchedule)
+            .expect("add SubstepSchedule first");
+
+        substeps.add_systems(
+            solve_constraint::<GenericJoint, 2>
+                .after(solve_constraint::<FixedJoint2, 2>)
+                .before(solve_constraint::<RevoluteJoint, 2>)
+                .in_set(SubstepSet::SolveConstraints),
+        );
+
+        substeps.add_systems(
+            joint_damping::<GenericJoint>
+                .after(joint_damping::<FixedJoint2>)
+                .before(joint_damping::<RevoluteJoint>)
+                .in_set(SubstepSet::SolveVelocities),
+        );
+    }
+}
+
+/// An axis of a joint frame.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum JointAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl JointAxis {
+    fn index(self) -> usize {
+        match self {
+            JointAxis::X => 0,
+            JointAxis::Y => 1,
+            JointAxis::Z => 2,
+        }
+    }
+}
+
+/// How the bodies can move relative to each other along or around one axis of the joint frame.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum AxisMode {
+    Free,
+    #[default]
+    Locked,
+    /// Free between `min` and `max`, in meters or radians.
+    Limited {
+        min: Scalar,
+        max: Scalar,
+    },
+}
+
+impl AxisMode {
+    /// The closest allowed value to `value`.
+    fn constrain(self, value: Scalar) -> Scalar {
+        match self {
+            AxisMode::Free => value,
+            AxisMode::Locked => 0.0,
+            AxisMode::Limited { min, max } => value.clamp(min, max),
+        }
+    }
+}
+
+/// Drives one axis of a joint. Motors are soft constraints of their own, applied after the axis
+/// modes, so a motor can't push an axis past its limits.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum JointMotor {
+    /// Drive the axis towards a position, in meters or radians.
+    Position { target: Scalar, compliance: Scalar },
+    /// Drive the axis at a velocity, in m/s or rad/s.
+    Velocity { target: Scalar, compliance: Scalar },
+}
+
+impl JointMotor {
+    fn compliance(&self) -> Scalar {
+        match self {
+            JointMotor::Position { compliance, .. } | JointMotor::Velocity { compliance, .. } => {
+                *compliance
+            }
+        }
+    }
+
+    /// Where the motor wants an axis at `position` moving with `velocity` to be after this substep.
+    fn target(&self, position: Scalar, velocity: Scalar, dt: Scalar) -> Scalar {
+        match self {
+            JointMotor::Position { target, .. } => *target,
+            // Positions were already integrated with the current velocity, so only correct the difference
+            JointMotor::Velocity { target, .. } => position + (target - velocity) * dt,
+        }
+    }
+}
+
+/// A joint constraining each axis of a joint frame attached to both bodies.
+///
+/// The joint frame sits at `local_anchor1` rotated by `local_frame1` on the first body, and at
+/// `local_anchor2` rotated by `local_frame2` on the second. Linear axes measure the second frame's
+/// offset from the first, angular axes the components of its rotation vector relative to the first.
+#[derive(Component, Reflect, Clone, Copy, Debug, PartialEq)]
+#[reflect(Component, MapEntities)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct GenericJoint {
+    /// First entity constrained by the joint.
+    pub entity1: Entity,
+    /// Second entity constrained by the joint.
+    pub entity2: Entity,
+    /// Attachment point on the first body.
+    pub local_anchor1: Vector,
+    /// Attachment point on the second body.
+    pub local_anchor2: Vector,
+    /// Orientation of the joint frame on the first body.
+    pub local_frame1: Quat,
+    /// Orientation of the joint frame on the second body.
+    pub local_frame2: Quat,
+    /// Modes of the X, Y and Z translational axes.
+    pub linear_axes: [AxisMode; 3],
+    /// Modes of the X, Y and Z rotational axes.
+    pub angular_axes: [AxisMode; 3],
+    pub linear_motors: [Option<JointMotor>; 3],
+    pub angular_motors: [Option<JointMotor>; 3],
+    /// Linear damping applied by the joint.
+    pub damping_linear: Scalar,
+    /// Angular damping applied by the joint.
+    pub damping_angular: Scalar,
+    /// Compliance of the locked and limited translational axes, has the unit meters / Newton.
+    pub linear_compliance: Scalar,
+    /// Compliance of the locked and limited rotational axes, has the unit radians / Newton meter.
+    pub angular_compliance: Scalar,
+    /// Lagrange multipliers for the axis modes and the motors.
+    pub position_lagrange: Scalar,
+    pub align_lagrange: Scalar,
+    pub linear_motor_lagrange: Scalar,
+    pub angular_motor_lagrange: Scalar,
+    /// The force exerted by the joint.
+    pub force: Vector,
+    /// The torque exerted by the joint when aligning the bodies.
+    pub align_torque: Vector,
+}
+
+impl XpbdConstraint<2> for GenericJoint {
+    fn entities(&self) -> [Entity; 2] {
+        [self.entity1, self.entity2]
+    }
+
+    fn clear_lagrange_multipliers(&mut self) {
+        self.position_lagrange = 0.0;
+        self.align_lagrange = 0.0;
+        self.linear_motor_lagrange = 0.0;
+        self.angular_motor_lagrange = 0.0;
+    }
+
+    fn solve(&mut self, bodies: [&mut RigidBodyQueryItem; 2], dt: Scalar) {
+        let [body1, body2] = bodies;
+
+        // Rotational axes
+        let frame1 = body1.rotation.0 * self.local_frame1;
+        let angles = self.angles(&body1.rotation, &body2.rotation);
+        let target = Vector::from_array(std::array::from_fn(|i| {
+            self.angular_axes[i].constrain(angles[i])
+        }));
+        self.align_torque = Vector::ZERO;
+        if target != angles {
+            let dq = self.delta_q(&body1.rotation, &body2.rotation, target);
+            let mut lagrange = self.align_lagrange;
+            self.align_torque = self.align_orientation(
+                body1,
+                body2,
+                dq,
+                &mut lagrange,
+                self.angular_compliance,
+                dt,
+            );
+            self.align_lagrange = lagrange;
+        }
+
+        if self.angular_motors.iter().any(Option::is_some) {
+            let angles = self.angles(&body1.rotation, &body2.rotation);
+            let velocities =
+                frame1.inverse() * (body2.angular_velocity.0 - body1.angular_velocity.0);
+            let mut compliance = 0.0;
+            let target =
+                Vector::from_array(std::array::from_fn(|i| match self.angular_motors[i] {
+                    Some(motor) => {
+                        compliance = motor.compliance();
+                        let target = motor.target(angles[i], velocities[i], dt);
+                        self.angular_axes[i].constrain(target)
+                    }
+                    None => angles[i],
+                }));
+            let dq = self.delta_q(&body1.rotation, &body2.rotation, target);
+            let mut lagrange = self.angular_motor_lagrange;
+            self.align_torque +=
+                self.align_orientation(body1, body2, dq, &mut lagrange, compliance, dt);
+            self.angular_motor_lagrange = lagrange;
+        }
+
+        // Translational axes, by moving the first anchor to where the second one should be
+        let offset = self.offset(body1, body2);
+        let target = Vector::from_array(std::array::from_fn(|i| {
+            self.linear_axes[i].constrain(offset[i])
+        }));
+        let mut lagrange = self.position_lagrange;
+        self.force = self.align_position(
+            body1,
+            body2,
+            self.local_anchor1 + self.local_frame1 * target,
+            self.local_anchor2,
+            &mut lagrange,
+            self.linear_compliance,
+            dt,
+        );
+        self.position_lagrange = lagrange;
+
+        if self.linear_motors.iter().any(Option::is_some) {
+            let frame1 = body1.rotation.0 * self.local_frame1;
+            let offset = self.offset(body1, body2);
+            let velocities = frame1.inverse() * (body2.linear_velocity.0 - body1.linear_velocity.0);
+            let mut compliance = 0.0;
+            let target = Vector::from_array(std::array::from_fn(|i| match self.linear_motors[i] {
+                Some(motor) => {
+                    compliance = motor.compliance();
+                    let target = motor.target(offset[i], velocities[i], dt);
+                    self.linear_axes[i].constrain(target)
+                }
+                None => offset[i],
+            }));
+            let mut lagrange = self.linear_motor_lagrange;
+            self.force += self.align_position(
+                body1,
+                body2,
+                self.local_anchor1 + self.local_frame1 * target,
+                self.local_anchor2,
+                &mut lagrange,
+                compliance,
+                dt,
+            );
+            self.linear_motor_lagrange = lagrange;
+        }
+    }
+}
+
+impl Joint for GenericJoint {
+    /// A joint with every axis locked, like a fixed joint.
+    fn new(entity1: Entity, entity2: Entity) -> Self {
+        Self {
+            entity1,
+            entity2,
+            local_anchor1: Vector::ZERO,
+            local_anchor2: Vector::ZERO,
+            local_frame1: Quat::IDENTITY,
+            local_frame2: Quat::IDENTITY,
+            linear_axes: [AxisMode::Locked; 3],
+            angular_axes: [AxisMode::Locked; 3],
+            linear_motors: [None; 3],
+            angular_motors: [None; 3],
+            damping_linear: 1.0,
+            damping_angular: 1.0,
+            linear_compliance: 0.0,
+            angular_compliance: 0.0,
+            position_lagrange: 0.0,
+            align_lagrange: 0.0,
+            linear_motor_lagrange: 0.0,
+            angular_motor_lagrange: 0.0,
+            force: Vector::ZERO,
+            align_torque: Vector::ZERO,
+        }
+    }
+
+    fn with_compliance(self, compliance: Scalar) -> Self {
+        Self {
+            linear_compliance: compliance,
+            angular_compliance: compliance,
+            ..self
+        }
+    }
+
+    fn with_local_anchor_1(self, anchor: Vector) -> Self {
+        Self {
+            local_anchor1: anchor,
+            ..self
+        }
+    }
+
+    fn with_local_anchor_2(self, anchor: Vector) -> Self {
+        Self {
+            local_anchor2: anchor,
+            ..self
+        }
+    }
+
+    fn with_linear_velocity_damping(self, damping: Scalar) -> Self {
+        Self {
+            damping_linear: damping,
+            ..self
+        }
+    }
+
+    fn with_angular_velocity_damping(self, damping: Scalar) -> Self {
+        Self {
+            damping_angular: damping,
+            ..self
+        }
+    }
+
+    fn local_anchor_1(&self) -> Vector {
+        self.local_anchor1
+    }
+
+    fn local_anchor_2(&self) -> Vector {
+        self.local_anchor2
+    }
+
+    fn damping_linear(&self) -> Scalar {
+        self.damping_linear
+    }
+
+    fn damping_angular(&self) -> Scalar {
+        self.damping_angular
+    }
+}
+
+// Needed to insert the joint from a scene, where the entities get mapped after insertion
+impl Default for GenericJoint {
+    fn default() -> Self {
+        Self::new(Entity::PLACEHOLDER, Entity::PLACEHOLDER)
+    }
+}
+
+impl GenericJoint {
+    /// Slides along `axis` and nothing else.
+    pub fn slider(entity1: Entity, entity2: Entity, axis: JointAxis) -> Self {
+        Self::new(entity1, entity2).with_linear_axis(axis, AxisMode::Free)
+    }
+
+    /// Rotates around `axis` and nothing else.
+    pub fn hinge(entity1: Entity, entity2: Entity, axis: JointAxis) -> Self {
+        Self::new(entity1, entity2).with_angular_axis(axis, AxisMode::Free)
+    }
+
+    /// Rotates freely around the anchors.
+    pub fn ball(entity1: Entity, entity2: Entity) -> Self {
+        Self {
+            angular_axes: [AxisMode::Free; 3],
+            ..Self::new(entity1, entity2)
+        }
+    }
+
+    /// Rotation offset from body 1 to body 2, like [`FixedJoint2::rotation_offset`].
+    pub fn with_rotation_offset(self, offset: Rotation) -> Self {
+        Self {
+            local_frame1: offset.normalize(),
+            local_frame2: Quat::IDENTITY,
+            ..self
+        }
+    }
+
+    pub fn with_local_frame_1(self, frame: Quat) -> Self {
+        Self {
+            local_frame1: frame.normalize(),
+            ..self
+        }
+    }
+
+    pub fn with_local_frame_2(self, frame: Quat) -> Self {
+        Self {
+            local_frame2: frame.normalize(),
+            ..self
+        }
+    }
+
+    pub fn with_linear_axis(mut self, axis: JointAxis, mode: AxisMode) -> Self {
+        self.linear_axes[axis.index()] = mode;
+        self
+    }
+
+    pub fn with_angular_axis(mut self, axis: JointAxis, mode: AxisMode) -> Self {
+        self.angular_axes[axis.index()] = mode;
+        self
+    }
+
+    /// Motors on different axes of the same kind share one constraint, and use the compliance of
+    /// the last of them.
+    pub fn with_linear_motor(mut self, axis: JointAxis, motor: JointMotor) -> Self {
+        self.linear_motors[axis.index()] = Some(motor);
+        self
+    }
+
+    pub fn with_angular_motor(mut self, axis: JointAxis, motor: JointMotor) -> Self {
+        self.angular_motors[axis.index()] = Some(motor);
+        self
+    }
+
+    pub fn with_linear_compliance(self, compliance: Scalar) -> Self {
+        Self {
+            linear_compliance: compliance,
+            ..self
+        }
+    }
+
+    pub fn with_angular_compliance(self, compliance: Scalar) -> Self {
+        Self {
+            angular_compliance: compliance,
+            ..self
+        }
+    }
+
+    /// The second anchor's offset from the first, in the first joint frame.
+    fn offset(&self, body1: &RigidBodyQueryItem, body2: &RigidBodyQueryItem) -> Vector {
+        let anchor1 = body1.current_position() + body1.rotation.rotate(self.local_anchor1);
+        let anchor2 = body2.current_position() + body2.rotation.rotate(self.local_anchor2);
+        (body1.rotation.0 * self.local_frame1).inverse() * (anchor2 - anchor1)
+    }
+
+    /// The second joint frame's rotation vector, in the first joint frame.
+    fn angles(&self, rot1: &Rotation, rot2: &Rotation) -> Vector {
+        let relative = (rot1.0 * self.local_frame1).inverse() * rot2.0 * self.local_frame2;
+        let relative = if relative.w < 0.0 {
+            -relative
+        } else {
+            relative
+        };
+        relative.to_scaled_axis()
+    }
+
+    /// The correction that brings the second body to the rotation vector `angles`.
+    fn delta_q(&self, rot1: &Rotation, rot2: &Rotation, angles: Vector) -> Vector {
+        let target = rot1.0
+            * self.local_frame1
+            * Quat::from_scaled_axis(angles)
+            * self.local_frame2.inverse();
+        let delta_q = target * rot2.inverse().0;
+        let delta_q = if delta_q.w < 0.0 { delta_q } else { -delta_q };
+        2.0 * delta_q.xyz()
+    }
+}
+
+impl PositionConstraint for GenericJoint {}
+
+impl AngularConstraint for GenericJoint {}
+
+impl MapEntities for GenericJoint {
+    fn map_entities(&mut self, entity_mapper: &mut EntityMapper) {
+        self.entity1 = entity_mapper.get_or_reserve(self.entity1);
+        self.entity2 = entity_mapper.get_or_reserve(self.entity2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::vr_hands::fixed_joint_2::FixedJoint2Plugin;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            HierarchyPlugin,
+            AssetPlugin::default(),
+            bevy::scene::ScenePlugin,
+        ))
+        .init_asset::<Mesh>()
+        // Ordered after the fixed joints, like in the app
+        .add_plugins((
+            PhysicsPlugins::default(),
+            FixedJoint2Plugin,
+            GenericJointPlugin,
+        ))
+        .insert_resource(Gravity(Vec3::ZERO))
+        // A physics step every update
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            1.0 / 60.0,
+        )));
+        app
+    }
+
+    // A static body at the origin, and a ball on the joint's second end, set moving
+    fn jointed(
+        app: &mut App,
+        joint: impl Fn(Entity, Entity) -> GenericJoint,
+        linear_velocity: Vec3,
+        angular_velocity: Vec3,
+    ) -> Entity {
+        let world = &mut app.world;
+        let base = world
+            .spawn((RigidBody::Static, TransformBundle::default()))
+            .id();
+        let ball = world
+            .spawn((
+                RigidBody::Dynamic,
+                Collider::ball(0.1),
+                TransformBundle::default(),
+                LinearVelocity(linear_velocity),
+                AngularVelocity(angular_velocity),
+            ))
+            .id();
+        world.spawn(joint(base, ball));
+        ball
+    }
+
+    fn run(app: &mut App, updates: usize) {
+        for _ in 0..updates {
+            app.update();
+        }
+    }
+
+    #[test]
+    fn slider_only_moves_along_its_axis() {
+        let mut app = app();
+        let ball = jointed(
+            &mut app,
+            |base, ball| GenericJoint::slider(base, ball, JointAxis::X),
+            Vec3::ONE,
+            Vec3::ONE,
+        );
+        run(&mut app, 60);
+
+        let position = app.world.get::<Position>(ball).unwrap().0;
+        let rotation = app.world.get::<Rotation>(ball).unwrap().0;
+        assert!(position.x > 0.1, "{position}");
+        assert!(
+            position.y.abs() < 1e-3 && position.z.abs() < 1e-3,
+            "{position}"
+        );
+        assert!(rotation.abs_diff_eq(Quat::IDENTITY, 1e-3), "{rotation}");
+    }
+
+    #[test]
+    fn hinge_stops_at_its_limit() {
+        let mut app = app();
+        let ball = jointed(
+            &mut app,
+            |base, ball| {
+                GenericJoint::hinge(base, ball, JointAxis::Y).with_angular_axis(
+                    JointAxis::Y,
+                    AxisMode::Limited {
+                        min: -0.5,
+                        max: 0.5,
+                    },
+                )
+            },
+            Vec3::ZERO,
+            Vec3::new(0.0, 3.0, 0.0),
+        );
+        // It swings into the limit well within the first second, and may bounce back off it
+        let mut furthest: f32 = 0.0;
+        for _ in 0..60 {
+            app.update();
+            let angles = app.world.get::<Rotation>(ball).unwrap().to_scaled_axis();
+            assert!(angles.y.abs() < 0.5 + 1e-2, "{angles}");
+            assert!(angles.x.abs() < 1e-3 && angles.z.abs() < 1e-3, "{angles}");
+            furthest = furthest.max(angles.y);
+        }
+        assert!(furthest > 0.5 - 1e-2, "{furthest}");
+    }
+
+    #[test]
+    fn velocity_motor_reaches_its_target_speed() {
+        let mut app = app();
+        let ball = jointed(
+            &mut app,
+            |base, ball| {
+                GenericJoint::slider(base, ball, JointAxis::X)
+                    .with_linear_motor(
+                        JointAxis::X,
+                        JointMotor::Velocity {
+                            target: 2.0,
+                            compliance: 0.0,
+                        },
+                    )
+                    .with_linear_velocity_damping(0.0)
+            },
+            Vec3::ZERO,
+            Vec3::ZERO,
+        );
+        run(&mut app, 30);
+
+        let velocity = app.world.get::<LinearVelocity>(ball).unwrap().0;
+        assert!((velocity.x - 2.0).abs() < 0.05, "{velocity}");
+        assert!(
+            velocity.y.abs() < 1e-3 && velocity.z.abs() < 1e-3,
+            "{velocity}"
+        );
+    }
+}
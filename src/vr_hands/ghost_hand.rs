@@ -0,0 +1,97 @@
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
+
+use super::velocity_tracking::VelocityTrackingSet;
+
+pub struct GhostHandPlugin<L = Update> {
+    /// The schedule the ghosts fade in, after [`VelocityTrackingSet`] there.
+    pub schedule: L,
+}
+
+impl<L: ScheduleLabel + Clone> GhostHandPlugin<L> {
+    pub fn new(schedule: L) -> Self {
+        Self { schedule }
+    }
+}
+
+impl Default for GhostHandPlugin {
+    fn default() -> Self {
+        Self::new(Update)
+    }
+}
+
+impl<L: ScheduleLabel + Clone> Plugin for GhostHandPlugin<L> {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            self.schedule.clone(),
+            fade_ghost_hands.after(VelocityTrackingSet),
+        );
+    }
+}
+
+/// A translucent stand-in for a physics hand, shown at the real controller pose while the physics
+/// hand is held back from it. Spawn it as a child of the hand's follow target.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct GhostHand {
+    /// The [`VelocityTracked`](super::velocity_tracking::VelocityTracked) hand this ghost stands in for.
+    pub hand: Entity,
+    /// Separation from the physics hand at which the ghost starts fading in.
+    pub fade_start: f32,
+    /// Separation from the physics hand at which the ghost is fully faded in.
+    pub fade_end: f32,
+    /// Opacity of the fully faded in ghost.
+    pub max_alpha: f32,
+}
+
+impl GhostHand {
+    pub fn new(hand: Entity) -> Self {
+        Self {
+            hand,
+            fade_start: 0.02,
+            fade_end: 0.1,
+            max_alpha: 0.4,
+        }
+    }
+
+    fn alpha(&self, separation: f32) -> f32 {
+        let fade =
+            (separation - self.fade_start) / (self.fade_end - self.fade_start).max(f32::EPSILON);
+        fade.clamp(0.0, 1.0) * self.max_alpha
+    }
+}
+
+fn fade_ghost_hands(
+    mut ghosts: Query<(
+        &GhostHand,
+        &GlobalTransform,
+        &Handle<StandardMaterial>,
+        &mut Visibility,
+    )>,
+    transforms: Query<&GlobalTransform>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (ghost, ghost_transform, material, mut visibility) in ghosts.iter_mut() {
+        let Ok(hand_transform) = transforms.get(ghost.hand) else {
+            continue;
+        };
+        let separation = ghost_transform
+            .translation()
+            .distance(hand_transform.translation());
+        let alpha = ghost.alpha(separation);
+
+        // Writing either marks it changed, which re-uploads the material or re-checks visibility
+        // every frame, so only write what actually changed
+        visibility.set_if_neq(if alpha > 0.0 {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+        let changed = materials
+            .get(material)
+            .is_some_and(|current| current.base_color.a() != alpha);
+        if changed {
+            if let Some(material) = materials.get_mut(material) {
+                material.base_color.set_a(alpha);
+            }
+        }
+    }
+}
@@ -1,31 +1,132 @@
-use bevy::prelude::*;
+use bevy::{
+    ecs::{
+        entity::{EntityMapper, MapEntities},
+        reflect::ReflectMapEntities,
+        schedule::ScheduleLabel,
+    },
+    prelude::*,
+    utils::HashSet,
+};
 use bevy_oxr::xr_input::Hand;
 use bevy_xpbd_3d::{
     plugins::collision::contact_query::{closest_points, ClosestPoints},
     prelude::*,
 };
 
-use super::fixed_joint_2::FixedJoint2;
+use super::{
+    fixed_joint_2::FixedJoint2,
+    tracking_state::{TrackingLossBehavior, TrackingLost},
+    velocity_tracking::{HandTeleported, TeleportHeldObjects, VelocityTrackingSet},
+};
+use crate::body_of;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Reflect, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum GrabberState {
+    #[default]
     Idle,
     Grabbing(Option<(Entity, Vec3)>),
     Grabbed(Entity, Entity),
 }
 
-#[derive(Component, Debug, Clone, Copy)]
+/// The hand a [`Grabber`] belongs to. A copy of bevy_oxr's [`Hand`], which isn't reflected.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum GrabberHand {
+    #[default]
+    Left,
+    Right,
+}
+
+impl From<Hand> for GrabberHand {
+    fn from(hand: Hand) -> Self {
+        match hand {
+            Hand::Left => GrabberHand::Left,
+            Hand::Right => GrabberHand::Right,
+        }
+    }
+}
+
+impl From<GrabberHand> for Hand {
+    fn from(hand: GrabberHand) -> Self {
+        match hand {
+            GrabberHand::Left => Hand::Left,
+            GrabberHand::Right => Hand::Right,
+        }
+    }
+}
+
+impl PartialEq<Hand> for GrabberHand {
+    fn eq(&self, other: &Hand) -> bool {
+        *self == GrabberHand::from(*other)
+    }
+}
+
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default, MapEntities)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct Grabber {
-    pub hand: Hand,
+    pub hand: GrabberHand,
     pub search_radius: f32,
     pub grab_tolerance: f32,
     pub grabbable_layer_mask: u32,
     pub state: GrabberState,
 }
 
-#[derive(Component, Debug, Clone)]
+impl Default for Grabber {
+    fn default() -> Self {
+        Self {
+            hand: GrabberHand::Left,
+            search_radius: 0.1,
+            grab_tolerance: 0.02,
+            grabbable_layer_mask: u32::MAX,
+            state: GrabberState::Idle,
+        }
+    }
+}
+
+impl MapEntities for Grabber {
+    fn map_entities(&mut self, entity_mapper: &mut EntityMapper) {
+        self.state = match self.state {
+            GrabberState::Idle => GrabberState::Idle,
+            GrabberState::Grabbing(target) => GrabberState::Grabbing(
+                target.map(|(entity, point)| (entity_mapper.get_or_reserve(entity), point)),
+            ),
+            GrabberState::Grabbed(grabbed, joint) => GrabberState::Grabbed(
+                entity_mapper.get_or_reserve(grabbed),
+                entity_mapper.get_or_reserve(joint),
+            ),
+        };
+    }
+}
+
+/// What happens when a grabber grabs an object that another grabber is already holding.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum TransferMode {
+    /// The new grabber takes the object, and the grabber holding it lets go.
+    Steal,
+    /// Both grabbers hold the object, like any grabbable did before transfer modes.
+    #[default]
+    TwoHanded,
+    /// The object can't be grabbed while it's held.
+    Refuse,
+}
+
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component, MapEntities)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct Grabbable {
     pub grabbed_by: Vec<Entity>,
+    pub transfer: TransferMode,
+}
+
+impl MapEntities for Grabbable {
+    fn map_entities(&mut self, entity_mapper: &mut EntityMapper) {
+        for entity in self.grabbed_by.iter_mut() {
+            *entity = entity_mapper.get_or_reserve(*entity);
+        }
+    }
 }
 
 #[derive(Event)]
@@ -38,21 +139,96 @@ pub struct EndGrabEvent {
     pub hand: Hand,
 }
 
-pub struct GrabberPlugin;
+/// Sent when a grabber lets go of an object it was holding with a joint.
+#[derive(Event)]
+pub struct GrabReleasedEvent {
+    pub grabber: Entity,
+    pub grabbed: Entity,
+}
+
+/// Sent when a grabber takes an object out of another grabber's hold.
+#[derive(Event)]
+pub struct GrabStolenEvent {
+    pub grabbed: Entity,
+    pub from: Entity,
+    pub to: Entity,
+}
 
-impl Plugin for GrabberPlugin {
+/// Sent when a grabber grabs an object that another grabber keeps holding.
+#[derive(Event)]
+pub struct TwoHandGrabEvent {
+    pub grabbed: Entity,
+    pub grabbers: Vec<Entity>,
+}
+
+/// Sent when a grabber tries to grab a held object that refuses transfers.
+#[derive(Event)]
+pub struct GrabRefusedEvent {
+    pub grabbable: Entity,
+    pub grabber: Entity,
+}
+
+/// The rigid bodies currently held by a grab joint.
+pub fn held_bodies(grabbers: &Query<&Grabber>) -> Vec<Entity> {
+    grabbers
+        .iter()
+        .filter_map(|grabber| match grabber.state {
+            GrabberState::Grabbed(entity, _) => Some(entity),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The systems that move [`Grabber`]s through their grab lifecycle. Send [`StartGrabEvent`]s and
+/// [`EndGrabEvent`]s before this set.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GrabberSet;
+
+pub struct GrabberPlugin<L = Update> {
+    /// The schedule [`GrabberSet`] runs in.
+    pub schedule: L,
+}
+
+impl<L: ScheduleLabel + Clone> GrabberPlugin<L> {
+    pub fn new(schedule: L) -> Self {
+        Self { schedule }
+    }
+}
+
+impl Default for GrabberPlugin {
+    fn default() -> Self {
+        Self::new(Update)
+    }
+}
+
+impl<L: ScheduleLabel + Clone> Plugin for GrabberPlugin<L> {
     fn build(&self, app: &mut App) {
-        app.add_event::<StartGrabEvent>()
+        app.register_type::<Grabber>()
+            .register_type::<GrabberHand>()
+            .register_type::<GrabberState>()
+            .register_type::<Option<(Entity, Vec3)>>()
+            .register_type::<(Entity, Vec3)>()
+            .register_type::<Grabbable>()
+            .register_type::<TransferMode>()
+            .register_type::<Vec<Entity>>()
+            .add_event::<StartGrabEvent>()
             .add_event::<EndGrabEvent>()
+            .add_event::<GrabReleasedEvent>()
+            .add_event::<GrabStolenEvent>()
+            .add_event::<TwoHandGrabEvent>()
+            .add_event::<GrabRefusedEvent>()
             .add_systems(
-                Update,
+                self.schedule.clone(),
                 (
                     (handle_grab_start, handle_grab_end),
+                    // Velocity tracking sends the teleport and tracking loss events
+                    (handle_hand_teleports, release_on_tracking_lost).after(VelocityTrackingSet),
                     update_grabbing_grabbers,
                     show_grab_point_gizmos,
                     grab_when_close_enough,
                 )
-                    .chain(),
+                    .chain()
+                    .in_set(GrabberSet),
             );
     }
 }
@@ -69,11 +245,13 @@ fn handle_grab_start(
     mut grabbers: Query<&mut Grabber>,
 ) {
     for event in grab_events.read() {
-        let mut grabber = grabbers
+        // A rig can leave out either hand
+        let Some(mut grabber) = grabbers
             .iter_mut()
-            .filter(|grabber| grabber.hand == event.hand)
-            .next()
-            .unwrap();
+            .find(|grabber| grabber.hand == event.hand)
+        else {
+            continue;
+        };
 
         assert!(
             matches!(grabber.state, GrabberState::Idle),
@@ -83,15 +261,29 @@ fn handle_grab_start(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn update_grabbing_grabbers(
     mut grabbers: Query<(Entity, &GlobalTransform, &mut Grabber)>,
     mut grabbables: Query<&mut Grabbable>,
     spatial_query: SpatialQuery,
     colliders: Query<(&Collider, &GlobalTransform)>,
     parents: Query<&Parent>,
+    rbs: Query<(), With<RigidBody>>,
+    mut refused_events: EventWriter<GrabRefusedEvent>,
+    // Grabber and grabbable pairs that were already refused, so it's only reported once per grab
+    mut refused: Local<HashSet<(Entity, Entity)>>,
 ) {
+    let held: Vec<(Entity, Entity)> = grabbers
+        .iter()
+        .filter_map(|(entity, _, grabber)| match grabber.state {
+            GrabberState::Grabbed(grabbed, _) => Some((entity, grabbed)),
+            _ => None,
+        })
+        .collect();
+
     for (grabber_entity, transform, mut grabber) in grabbers.iter_mut() {
         if !matches!(grabber.state, GrabberState::Grabbing(_)) {
+            refused.retain(|(grabber, _)| *grabber != grabber_entity);
             continue;
         }
 
@@ -112,7 +304,7 @@ fn update_grabbing_grabbers(
                 &test_collider,
                 transform.translation,
                 transform.rotation,
-                &cand_collider,
+                cand_collider,
                 cand_transform.translation,
                 cand_transform.rotation,
                 grabber.search_radius,
@@ -136,7 +328,7 @@ fn update_grabbing_grabbers(
             .min_by(|(_, d1, _), (_, d2, _)| d1.partial_cmp(d2).unwrap())
         {
             // Walk up the hierarchy to find the closest grabbable parent
-            while !grabbables.get(closest_candidate).is_ok() {
+            while grabbables.get(closest_candidate).is_err() {
                 closest_candidate = parents.get(closest_candidate).unwrap().get();
             }
 
@@ -144,10 +336,28 @@ fn update_grabbing_grabbers(
                 grabbable.grabbed_by.retain(|e| *e != grabber_entity);
             }
 
+            let body = body_of(closest_candidate, &rbs, &parents);
+            let held_by_other = held
+                .iter()
+                .any(|(other, grabbed)| *other != grabber_entity && Some(*grabbed) == body);
+            if held_by_other
+                && grabbables.get(closest_candidate).unwrap().transfer == TransferMode::Refuse
+            {
+                if refused.insert((grabber_entity, closest_candidate)) {
+                    debug!("Refused grab of {:?}", closest_candidate);
+                    refused_events.send(GrabRefusedEvent {
+                        grabbable: closest_candidate,
+                        grabber: grabber_entity,
+                    });
+                }
+                grabber.state = GrabberState::Grabbing(None);
+                continue;
+            }
+
             let mut closest_grabbable = grabbables.get_mut(closest_candidate).unwrap();
             closest_grabbable.grabbed_by.push(grabber_entity);
 
-            println!("Grabbing {:?}", closest_candidate);
+            debug!("Grabbing {:?}", closest_candidate);
             grabber.state = GrabberState::Grabbing(Some((closest_candidate, closest_point)));
         } else {
             for mut grabbable in grabbables.iter_mut() {
@@ -158,15 +368,28 @@ fn update_grabbing_grabbers(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn grab_when_close_enough(
     mut commands: Commands,
     mut grabbers: Query<(Entity, &GlobalTransform, &mut Grabber)>,
+    mut grabbables: Query<&mut Grabbable>,
     transforms: Query<&GlobalTransform>,
     children: Query<&Parent>,
     rbs: Query<(), With<RigidBody>>,
+    mut stolen_events: EventWriter<GrabStolenEvent>,
+    mut two_hand_events: EventWriter<TwoHandGrabEvent>,
 ) {
+    let mut held: Vec<(Entity, Entity, Entity)> = grabbers
+        .iter()
+        .filter_map(|(entity, _, grabber)| match grabber.state {
+            GrabberState::Grabbed(grabbed, joint) => Some((entity, grabbed, joint)),
+            _ => None,
+        })
+        .collect();
+    let mut stolen_from = vec![];
+
     for (grabber_entity, grabber_transform, mut grabber) in grabbers.iter_mut() {
-        let GrabberState::Grabbing(Some((grabbed_entity, closest_point))) = grabber.state else {
+        let GrabberState::Grabbing(Some((grabbable_entity, closest_point))) = grabber.state else {
             continue;
         };
         if grabber_transform.translation().distance(closest_point) >= grabber.grab_tolerance {
@@ -174,7 +397,7 @@ fn grab_when_close_enough(
         }
 
         let joint_config_for = |mut entity: Entity| -> (Entity, Vec3, Quat) {
-            while !rbs.get(entity).is_ok() {
+            while rbs.get(entity).is_err() {
                 entity = children.get(entity).unwrap().get();
             }
             let transform = transforms.get(entity).unwrap();
@@ -183,10 +406,48 @@ fn grab_when_close_enough(
             (entity, local_anchor, rotation)
         };
 
-        let (grabber_entity, grabber_local_anchor, grabber_rotation) =
+        let (grabber_body, grabber_local_anchor, grabber_rotation) =
             joint_config_for(grabber_entity);
         let (grabbed_entity, grabbed_local_anchor, grabbed_rotation) =
-            joint_config_for(grabbed_entity);
+            joint_config_for(grabbable_entity);
+
+        let mut grabbable = grabbables.get_mut(grabbable_entity).unwrap();
+        let other_holders: Vec<(Entity, Entity)> = held
+            .iter()
+            .filter(|(_, grabbed, _)| *grabbed == grabbed_entity)
+            .map(|(holder, _, joint)| (*holder, *joint))
+            .collect();
+        if !other_holders.is_empty() {
+            match grabbable.transfer {
+                TransferMode::Steal => {
+                    for (holder, joint) in other_holders.iter().copied() {
+                        debug!("Stealing {:?} from {:?}", grabbed_entity, holder);
+                        commands.entity(joint).despawn_recursive();
+                        grabbable.grabbed_by.retain(|e| *e != holder);
+                        held.retain(|(e, _, _)| *e != holder);
+                        stolen_from.push(holder);
+                        stolen_events.send(GrabStolenEvent {
+                            grabbed: grabbed_entity,
+                            from: holder,
+                            to: grabber_entity,
+                        });
+                    }
+                }
+                TransferMode::TwoHanded => {
+                    let mut holders = vec![grabber_entity];
+                    holders.extend(other_holders.iter().map(|(holder, _)| *holder));
+                    two_hand_events.send(TwoHandGrabEvent {
+                        grabbed: grabbed_entity,
+                        grabbers: holders,
+                    });
+                }
+                // Grabbed by another grabber since this one started reaching for it
+                TransferMode::Refuse => {
+                    grabber.state = GrabberState::Grabbing(None);
+                    continue;
+                }
+            }
+        }
 
         // grabber_rotation * x = grabbed_rotation
         // x = grabber_rotation.inverse() * grabbed_rotation
@@ -195,7 +456,7 @@ fn grab_when_close_enough(
         // Create a joint between the grabber and the grabbed object
         let joint_id = commands
             .spawn((
-                FixedJoint2::new(grabber_entity, grabbed_entity)
+                FixedJoint2::new(grabber_body, grabbed_entity)
                     .with_local_anchor_1(grabber_local_anchor)
                     .with_local_anchor_2(grabbed_local_anchor)
                     .with_rotation_offset(rotation_offset.into()),
@@ -203,32 +464,40 @@ fn grab_when_close_enough(
             ))
             .id();
 
-        println!("Grabbed {:?}", grabbed_entity);
+        debug!("Grabbed {:?}", grabbed_entity);
         grabber.state = GrabberState::Grabbed(grabbed_entity, joint_id);
+        held.push((grabber_entity, grabbed_entity, joint_id));
+    }
+
+    for holder in stolen_from {
+        let (_, _, mut grabber) = grabbers.get_mut(holder).unwrap();
+        grabber.state = GrabberState::Idle;
     }
 }
 
 fn handle_grab_end(
     mut commands: Commands,
     mut grab_events: EventReader<EndGrabEvent>,
+    mut released_events: EventWriter<GrabReleasedEvent>,
     mut grabbers: Query<(Entity, &mut Grabber)>,
     mut grabbable: Query<&mut Grabbable>,
 ) {
     for event in grab_events.read() {
-        let (grabber_entity, mut grabber) = grabbers
+        let Some((grabber_entity, mut grabber)) = grabbers
             .iter_mut()
-            .filter(|(_, grabber)| grabber.hand == event.hand)
-            .next()
-            .unwrap();
-
-        assert!(
-            !matches!(grabber.state, GrabberState::Idle),
-            "Ended grab, but grabber was not grabbing."
-        );
+            .find(|(_, grabber)| grabber.hand == event.hand)
+        else {
+            continue;
+        };
 
+        // The grabber is already idle if the object it was holding got stolen
         let grabbed_entity = match grabber.state {
             GrabberState::Grabbed(entity, joint) => {
                 commands.entity(joint).despawn_recursive();
+                released_events.send(GrabReleasedEvent {
+                    grabber: grabber_entity,
+                    grabbed: entity,
+                });
                 Some(entity)
             }
             GrabberState::Grabbing(Some((entity, _))) => Some(entity),
@@ -238,13 +507,146 @@ fn handle_grab_end(
 
         grabber.state = GrabberState::Idle;
         if let Some(grabbed_entity) = grabbed_entity {
-            println!("Releasing {:?}", grabbed_entity);
+            debug!("Releasing {:?}", grabbed_entity);
             let mut grabbable = grabbable.get_mut(grabbed_entity).unwrap();
             grabbable.grabbed_by.retain(|e| *e != grabber_entity);
         }
     }
 }
 
+#[allow(clippy::type_complexity)]
+#[allow(clippy::too_many_arguments)]
+fn handle_hand_teleports(
+    mut commands: Commands,
+    mut teleported_events: EventReader<HandTeleported>,
+    mut released_events: EventWriter<GrabReleasedEvent>,
+    mut grabbers: Query<(Entity, &mut Grabber)>,
+    mut grabbables: Query<&mut Grabbable>,
+    mut held_bodies: Query<
+        (
+            &GlobalTransform,
+            &mut Transform,
+            &mut Position,
+            &mut Rotation,
+            &mut LinearVelocity,
+            &mut AngularVelocity,
+        ),
+        Without<Grabber>,
+    >,
+    global_transforms: Query<&GlobalTransform>,
+    rbs: Query<(), With<RigidBody>>,
+    parents: Query<&Parent>,
+) {
+    for event in teleported_events.read() {
+        for (grabber_entity, mut grabber) in grabbers.iter_mut() {
+            let GrabberState::Grabbed(held, _) = grabber.state else {
+                continue;
+            };
+            if body_of(grabber_entity, &rbs, &parents) != Some(event.hand) {
+                continue;
+            }
+
+            match event.held_objects {
+                TeleportHeldObjects::Release => {
+                    debug!("Releasing {:?} after teleport", held);
+                    release_held(
+                        &mut commands,
+                        grabber_entity,
+                        &mut grabber,
+                        &mut grabbables,
+                        &mut released_events,
+                    );
+                }
+                TeleportHeldObjects::BringAlong => {
+                    let Ok((
+                        global_transform,
+                        mut transform,
+                        mut position,
+                        mut rotation,
+                        mut linear_velocity,
+                        mut angular_velocity,
+                    )) = held_bodies.get_mut(held)
+                    else {
+                        continue;
+                    };
+
+                    // Apply the hand's jump to the held object, so it stays put relative to the hand
+                    let delta = event.to.compute_affine() * event.from.compute_affine().inverse();
+                    let new_global = GlobalTransform::from(delta * global_transform.affine());
+                    let new_local = match parents
+                        .get(held)
+                        .ok()
+                        .and_then(|parent| global_transforms.get(parent.get()).ok())
+                    {
+                        Some(parent_global) => new_global.reparented_to(parent_global),
+                        None => new_global.compute_transform(),
+                    };
+                    let new_global = new_global.compute_transform();
+                    transform.translation = new_local.translation;
+                    transform.rotation = new_local.rotation;
+                    position.0 = new_global.translation;
+                    rotation.0 = new_global.rotation;
+                    linear_velocity.0 = Vec3::ZERO;
+                    angular_velocity.0 = Vec3::ZERO;
+                }
+            }
+        }
+    }
+}
+
+fn release_on_tracking_lost(
+    mut commands: Commands,
+    mut lost_events: EventReader<TrackingLost>,
+    mut released_events: EventWriter<GrabReleasedEvent>,
+    mut grabbers: Query<(Entity, &mut Grabber)>,
+    mut grabbables: Query<&mut Grabbable>,
+    rbs: Query<(), With<RigidBody>>,
+    parents: Query<&Parent>,
+) {
+    for event in lost_events.read() {
+        if event.behavior != TrackingLossBehavior::Release {
+            continue;
+        }
+        for (grabber_entity, mut grabber) in grabbers.iter_mut() {
+            if body_of(grabber_entity, &rbs, &parents) != Some(event.hand) {
+                continue;
+            }
+            if let GrabberState::Grabbed(held, _) = grabber.state {
+                debug!("Releasing {:?} after losing tracking", held);
+                release_held(
+                    &mut commands,
+                    grabber_entity,
+                    &mut grabber,
+                    &mut grabbables,
+                    &mut released_events,
+                );
+            }
+        }
+    }
+}
+
+/// Lets go of whatever `grabber` is holding, as if the grab had ended.
+fn release_held(
+    commands: &mut Commands,
+    grabber_entity: Entity,
+    grabber: &mut Grabber,
+    grabbables: &mut Query<&mut Grabbable>,
+    released_events: &mut EventWriter<GrabReleasedEvent>,
+) {
+    let GrabberState::Grabbed(held, joint) = grabber.state else {
+        return;
+    };
+    commands.entity(joint).despawn_recursive();
+    if let Ok(mut grabbable) = grabbables.get_mut(held) {
+        grabbable.grabbed_by.retain(|e| *e != grabber_entity);
+    }
+    released_events.send(GrabReleasedEvent {
+        grabber: grabber_entity,
+        grabbed: held,
+    });
+    grabber.state = GrabberState::Idle;
+}
+
 // Show a gizmo for each grab point
 fn show_grab_point_gizmos(grabbers: Query<&Grabber>, mut gizmos: Gizmos) {
     for grabber in grabbers.iter() {
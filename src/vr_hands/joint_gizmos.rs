@@ -0,0 +1,192 @@
+//! Gizmos showing where joints attach and how hard they pull, for diagnosing unstable grabs.
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use super::{fixed_joint_2::FixedJoint2, generic_joint::GenericJoint};
+
+pub struct JointGizmosPlugin;
+
+impl Plugin for JointGizmosPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<JointGizmos>().add_systems(
+            PostUpdate,
+            (
+                draw_joint_gizmos::<FixedJoint2>,
+                draw_joint_gizmos::<GenericJoint>,
+                draw_joint_gizmos::<FixedJoint>,
+                draw_joint_gizmos::<RevoluteJoint>,
+                draw_joint_gizmos::<SphericalJoint>,
+                draw_joint_gizmos::<PrismaticJoint>,
+                draw_joint_gizmos::<DistanceJoint>,
+            )
+                .run_if(|gizmos: Res<JointGizmos>| gizmos.enabled),
+        );
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct JointGizmos {
+    pub enabled: bool,
+    /// Length of the force vector per Newton.
+    pub force_scale: f32,
+    /// Length of the torque vector per Newton meter.
+    pub torque_scale: f32,
+    /// Length of the joint frame axes.
+    pub frame_size: f32,
+}
+
+impl Default for JointGizmos {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            force_scale: 0.01,
+            torque_scale: 0.1,
+            frame_size: 0.05,
+        }
+    }
+}
+
+/// What a joint can show beyond its anchors. Joints that don't track something leave it out.
+pub trait JointGizmoInfo: Joint {
+    /// The force the joint exerted in the last substep.
+    fn force(&self) -> Option<Vec3> {
+        None
+    }
+
+    /// The torque the joint exerted in the last substep.
+    fn torque(&self) -> Option<Vec3> {
+        None
+    }
+
+    /// The orientations of the joint frame on each body, given the bodies' rotations. They match
+    /// when the joint's angular constraint is satisfied.
+    fn frames(&self, _rotation1: Quat, _rotation2: Quat) -> Option<(Quat, Quat)> {
+        None
+    }
+}
+
+impl JointGizmoInfo for FixedJoint2 {
+    fn force(&self) -> Option<Vec3> {
+        Some(self.force)
+    }
+
+    fn torque(&self) -> Option<Vec3> {
+        Some(self.align_torque)
+    }
+
+    fn frames(&self, rotation1: Quat, rotation2: Quat) -> Option<(Quat, Quat)> {
+        Some((rotation1 * self.rotation_offset.0, rotation2))
+    }
+}
+
+impl JointGizmoInfo for GenericJoint {
+    fn force(&self) -> Option<Vec3> {
+        Some(self.force)
+    }
+
+    fn torque(&self) -> Option<Vec3> {
+        Some(self.align_torque)
+    }
+
+    fn frames(&self, rotation1: Quat, rotation2: Quat) -> Option<(Quat, Quat)> {
+        Some((rotation1 * self.local_frame1, rotation2 * self.local_frame2))
+    }
+}
+
+impl JointGizmoInfo for FixedJoint {
+    fn force(&self) -> Option<Vec3> {
+        Some(self.force)
+    }
+
+    fn torque(&self) -> Option<Vec3> {
+        Some(self.align_torque)
+    }
+}
+
+impl JointGizmoInfo for RevoluteJoint {
+    fn force(&self) -> Option<Vec3> {
+        Some(self.force)
+    }
+
+    fn torque(&self) -> Option<Vec3> {
+        Some(self.align_torque)
+    }
+}
+
+impl JointGizmoInfo for SphericalJoint {
+    fn force(&self) -> Option<Vec3> {
+        Some(self.force)
+    }
+
+    /// Only the swing and twist limits exert torque, so this is zero while they aren't reached.
+    fn torque(&self) -> Option<Vec3> {
+        Some(self.swing_torque + self.twist_torque)
+    }
+}
+
+impl JointGizmoInfo for PrismaticJoint {
+    fn force(&self) -> Option<Vec3> {
+        Some(self.force)
+    }
+
+    fn torque(&self) -> Option<Vec3> {
+        Some(self.align_torque)
+    }
+}
+
+impl JointGizmoInfo for DistanceJoint {
+    fn force(&self) -> Option<Vec3> {
+        Some(self.force)
+    }
+}
+
+fn draw_joint_gizmos<J: JointGizmoInfo + XpbdConstraint<2> + Component>(
+    joints: Query<&J>,
+    bodies: Query<(&Position, &Rotation)>,
+    settings: Res<JointGizmos>,
+    mut gizmos: Gizmos,
+) {
+    for joint in joints.iter() {
+        let [entity1, entity2] = joint.entities();
+        let (Ok((position1, rotation1)), Ok((position2, rotation2))) =
+            (bodies.get(entity1), bodies.get(entity2))
+        else {
+            continue;
+        };
+        let anchor1 = position1.0 + rotation1.rotate(joint.local_anchor_1());
+        let anchor2 = position2.0 + rotation2.rotate(joint.local_anchor_2());
+
+        gizmos.line(position1.0, anchor1, Color::GRAY);
+        gizmos.line(position2.0, anchor2, Color::GRAY);
+        gizmos.sphere(anchor1, Quat::IDENTITY, 0.01, Color::CYAN);
+        gizmos.sphere(anchor2, Quat::IDENTITY, 0.01, Color::FUCHSIA);
+        // The positional constraint error
+        gizmos.line(anchor1, anchor2, Color::RED);
+
+        if let Some((frame1, frame2)) = joint.frames(rotation1.0, rotation2.0) {
+            draw_frame(&mut gizmos, anchor1, frame1, settings.frame_size);
+            draw_frame(&mut gizmos, anchor2, frame2, settings.frame_size * 0.5);
+        }
+        if let Some(force) = joint.force() {
+            gizmos.line(
+                anchor2,
+                anchor2 + force * settings.force_scale,
+                Color::YELLOW,
+            );
+        }
+        if let Some(torque) = joint.torque() {
+            gizmos.line(
+                anchor2,
+                anchor2 + torque * settings.torque_scale,
+                Color::ORANGE,
+            );
+        }
+    }
+}
+
+fn draw_frame(gizmos: &mut Gizmos, origin: Vec3, rotation: Quat, size: f32) {
+    gizmos.line(origin, origin + rotation * Vec3::X * size, Color::RED);
+    gizmos.line(origin, origin + rotation * Vec3::Y * size, Color::GREEN);
+    gizmos.line(origin, origin + rotation * Vec3::Z * size, Color::BLUE);
+}
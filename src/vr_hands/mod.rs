@@ -1,15 +1,51 @@
-use bevy::prelude::*;
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
 
+pub mod detach;
 pub mod fixed_joint_2;
+pub mod generic_joint;
+pub mod ghost_hand;
 pub mod grabber;
+pub mod joint_gizmos;
+pub mod pose_filter;
+pub mod pose_prediction;
+pub mod rig;
+pub mod tracking_state;
+pub mod usable;
 pub mod velocity_tracking;
 
-pub struct VrHandsPlugin;
+pub struct VrHandsPlugin<T = Update, G = Update> {
+    /// The schedule hand tracking runs in, see [`velocity_tracking::VelocityTrackingSet`].
+    pub tracking_schedule: T,
+    /// The schedule grabbing runs in, see [`grabber::GrabberSet`].
+    pub grab_schedule: G,
+}
+
+impl<T: ScheduleLabel + Clone, G: ScheduleLabel + Clone> VrHandsPlugin<T, G> {
+    pub fn new(tracking_schedule: T, grab_schedule: G) -> Self {
+        Self {
+            tracking_schedule,
+            grab_schedule,
+        }
+    }
+}
+
+impl Default for VrHandsPlugin {
+    fn default() -> Self {
+        Self::new(Update, Update)
+    }
+}
 
-impl Plugin for VrHandsPlugin {
+impl<T: ScheduleLabel + Clone, G: ScheduleLabel + Clone> Plugin for VrHandsPlugin<T, G> {
     fn build(&self, app: &mut App) {
-        app.add_plugins(grabber::GrabberPlugin)
-            .add_plugins(velocity_tracking::VelocityTrackingPlugin)
-            .add_plugins(fixed_joint_2::FixedJoint2Plugin);
+        app.add_plugins(grabber::GrabberPlugin::new(self.grab_schedule.clone()))
+            .add_plugins(velocity_tracking::VelocityTrackingPlugin::new(
+                self.tracking_schedule.clone(),
+            ))
+            .add_plugins(fixed_joint_2::FixedJoint2Plugin)
+            .add_plugins(generic_joint::GenericJointPlugin)
+            .add_plugins(usable::UsablePlugin::new(self.grab_schedule.clone()))
+            .add_plugins(ghost_hand::GhostHandPlugin::new(
+                self.tracking_schedule.clone(),
+            ));
     }
 }
@@ -0,0 +1,199 @@
+//! Smoothing filters for tracked controller poses, to keep controller jitter out of the hands and
+//! whatever they hold.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use super::pose_prediction::estimate_velocity;
+
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum PoseFilter {
+    #[default]
+    None,
+    /// Exponential smoothing with a cutoff frequency that rises with speed, from `min_cutoff` at
+    /// rest to `max_cutoff` at `speed_for_max_cutoff` m/s or `angular_speed_for_max_cutoff` rad/s.
+    Exponential {
+        min_cutoff: f32,
+        max_cutoff: f32,
+        speed_for_max_cutoff: f32,
+        angular_speed_for_max_cutoff: f32,
+    },
+    /// The 1€ filter: the cutoff frequency is `min_cutoff` plus `beta` times the speed, which is
+    /// itself smoothed with `derivative_cutoff`.
+    OneEuro {
+        min_cutoff: f32,
+        beta: f32,
+        derivative_cutoff: f32,
+    },
+}
+
+impl PoseFilter {
+    pub fn one_euro() -> Self {
+        Self::OneEuro {
+            min_cutoff: 1.0,
+            beta: 5.0,
+            derivative_cutoff: 1.0,
+        }
+    }
+
+    /// Heavier smoothing for precise placement.
+    pub fn precision() -> Self {
+        Self::OneEuro {
+            min_cutoff: 0.3,
+            beta: 0.5,
+            derivative_cutoff: 1.0,
+        }
+    }
+
+    /// Filters the pose measured `dt` seconds after the last one.
+    pub fn apply(&self, state: &mut PoseFilterState, measured: Transform, dt: f32) -> Transform {
+        let Some(previous) = state.pose else {
+            state.pose = Some(measured);
+            return measured;
+        };
+        if dt <= 0.0 {
+            return previous;
+        }
+
+        let (linear_velocity, angular_velocity) = estimate_velocity(previous, measured, dt);
+        let (linear_cutoff, angular_cutoff) = match *self {
+            PoseFilter::None => {
+                state.pose = Some(measured);
+                return measured;
+            }
+            PoseFilter::Exponential {
+                min_cutoff,
+                max_cutoff,
+                speed_for_max_cutoff,
+                angular_speed_for_max_cutoff,
+            } => {
+                let cutoff = |speed: f32, speed_for_max: f32| {
+                    let t = (speed / speed_for_max).clamp(0.0, 1.0);
+                    min_cutoff + (max_cutoff - min_cutoff) * t
+                };
+                (
+                    cutoff(linear_velocity.length(), speed_for_max_cutoff),
+                    cutoff(angular_velocity.length(), angular_speed_for_max_cutoff),
+                )
+            }
+            PoseFilter::OneEuro {
+                min_cutoff,
+                beta,
+                derivative_cutoff,
+            } => {
+                let alpha = smoothing_factor(derivative_cutoff, dt);
+                state.linear_speed += alpha * (linear_velocity.length() - state.linear_speed);
+                state.angular_speed += alpha * (angular_velocity.length() - state.angular_speed);
+                (
+                    min_cutoff + beta * state.linear_speed,
+                    min_cutoff + beta * state.angular_speed,
+                )
+            }
+        };
+
+        let pose = Transform {
+            translation: previous
+                .translation
+                .lerp(measured.translation, smoothing_factor(linear_cutoff, dt)),
+            rotation: previous
+                .rotation
+                .slerp(measured.rotation, smoothing_factor(angular_cutoff, dt)),
+            scale: measured.scale,
+        };
+        state.pose = Some(pose);
+        pose
+    }
+}
+
+/// What a [`PoseFilter`] remembers between frames.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoseFilterState {
+    pose: Option<Transform>,
+    linear_speed: f32,
+    angular_speed: f32,
+}
+
+/// While present on a [`VelocityTracked`](super::velocity_tracking::VelocityTracked) body, its
+/// target is smoothed with the `precision_filter` instead of the regular one.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct PrecisionMode;
+
+// The blend factor of a first order low-pass filter with the given cutoff frequency, in Hz
+fn smoothing_factor(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1.0 / (2.0 * PI * cutoff.max(f32::EPSILON));
+    1.0 / (1.0 + tau / dt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 72.0;
+
+    fn filters() -> [PoseFilter; 3] {
+        [
+            PoseFilter::one_euro(),
+            PoseFilter::precision(),
+            PoseFilter::Exponential {
+                min_cutoff: 1.0,
+                max_cutoff: 10.0,
+                speed_for_max_cutoff: 1.0,
+                angular_speed_for_max_cutoff: 5.0,
+            },
+        ]
+    }
+
+    /// Deterministic noise between -1 and 1.
+    fn noise(seed: &mut u32) -> f32 {
+        *seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (*seed >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+
+    #[test]
+    fn settles_on_steady_input() {
+        let start = Transform::from_xyz(0.2, 1.0, -0.3);
+        let steady = Transform::from_xyz(0.3, 1.1, -0.4).with_rotation(Quat::from_rotation_z(0.5));
+        for filter in filters() {
+            let mut state = PoseFilterState::default();
+            filter.apply(&mut state, start, DT);
+            let mut filtered = start;
+            // Two seconds of holding the controller still somewhere else
+            for _ in 0..144 {
+                filtered = filter.apply(&mut state, steady, DT);
+            }
+            assert!(
+                filtered.translation.abs_diff_eq(steady.translation, 1e-3),
+                "{filter:?}"
+            );
+            assert!(
+                filtered.rotation.angle_between(steady.rotation) < 1e-3,
+                "{filter:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn attenuates_jitter() {
+        // A controller held still, with a millimeter of tracking noise
+        let still = Transform::from_xyz(0.1, 1.2, -0.4);
+        for filter in filters() {
+            let mut state = PoseFilterState::default();
+            let mut seed = 1;
+            let (mut measured_error, mut filtered_error) = (0.0, 0.0);
+            for i in 0..144 {
+                let jitter = Vec3::new(noise(&mut seed), noise(&mut seed), noise(&mut seed));
+                let measured = Transform::from_translation(still.translation + jitter * 1e-3)
+                    .with_rotation(Quat::from_rotation_x(noise(&mut seed) * 0.005));
+                let filtered = filter.apply(&mut state, measured, DT);
+                // Past the first frames, where the filter starts out on a noisy sample
+                if i >= 36 {
+                    measured_error += measured.translation.distance(still.translation);
+                    filtered_error += filtered.translation.distance(still.translation);
+                }
+            }
+            assert!(filtered_error < measured_error * 0.5, "{filter:?}");
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Extrapolating controller poses ahead in time, so physics hands don't visibly trail the
+//! controllers during fast motion.
+
+use bevy::prelude::*;
+
+/// How far ahead a [`PosePrediction`] extrapolates.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum PredictionHorizon {
+    /// A fixed time ahead, in seconds. Use the display latency to match when the frame is shown.
+    Seconds(f32),
+    /// The duration of the last physics step, to match when the hand reaches the pose.
+    PhysicsStep,
+}
+
+/// Extrapolates a tracked pose at its estimated velocity. The horizon and speeds are clamped, so
+/// a hitch or a tracking glitch can't throw the predicted pose far from the measured one.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct PosePrediction {
+    pub horizon: PredictionHorizon,
+    /// The furthest ahead to extrapolate, in seconds.
+    pub max_horizon: f32,
+    /// The fastest linear velocity to extrapolate with.
+    pub max_speed: f32,
+    /// The fastest angular velocity to extrapolate with, in radians per second.
+    pub max_angular_speed: f32,
+}
+
+impl Default for PosePrediction {
+    fn default() -> Self {
+        Self {
+            horizon: PredictionHorizon::PhysicsStep,
+            max_horizon: 0.03,
+            max_speed: 3.0,
+            max_angular_speed: 15.0,
+        }
+    }
+}
+
+impl PosePrediction {
+    /// Extrapolates `pose` by `horizon` seconds at constant velocity, clamped to the prediction's
+    /// limits.
+    pub fn predict(
+        &self,
+        pose: Transform,
+        linear_velocity: Vec3,
+        angular_velocity: Vec3,
+        horizon: f32,
+    ) -> Transform {
+        let horizon = horizon.clamp(0.0, self.max_horizon);
+        let offset = linear_velocity.clamp_length_max(self.max_speed) * horizon;
+        let rotation = angular_velocity.clamp_length_max(self.max_angular_speed) * horizon;
+        Transform {
+            translation: pose.translation + offset,
+            rotation: (Quat::from_scaled_axis(rotation) * pose.rotation).normalize(),
+            scale: pose.scale,
+        }
+    }
+}
+
+/// Estimates the linear and angular velocity between two poses `dt` seconds apart.
+pub fn estimate_velocity(from: Transform, to: Transform, dt: f32) -> (Vec3, Vec3) {
+    if dt <= 0.0 {
+        return (Vec3::ZERO, Vec3::ZERO);
+    }
+
+    let linear_velocity = (to.translation - from.translation) / dt;
+    let delta_rotation = to.rotation * from.rotation.inverse();
+    // Take the short way around
+    let delta_rotation = if delta_rotation.w < 0.0 {
+        -delta_rotation
+    } else {
+        delta_rotation
+    };
+    let angular_velocity = delta_rotation.to_scaled_axis() / dt;
+    (linear_velocity, angular_velocity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 72.0;
+
+    /// A controller pose sampled every [`DT`] seconds.
+    fn record(samples: usize, pose_at: impl Fn(f32) -> Transform) -> Vec<Transform> {
+        (0..samples).map(|i| pose_at(i as f32 * DT)).collect()
+    }
+
+    /// Feeds a trace through velocity estimation and prediction one sample ahead, returning how
+    /// far each prediction and each measured pose is from the next sample.
+    fn prediction_errors(prediction: &PosePrediction, trace: &[Transform]) -> Vec<(f32, f32)> {
+        trace
+            .windows(3)
+            .map(|window| {
+                let [previous, current, next] = [window[0], window[1], window[2]];
+                let (linear_velocity, angular_velocity) = estimate_velocity(previous, current, DT);
+                let predicted = prediction.predict(current, linear_velocity, angular_velocity, DT);
+                (
+                    predicted.translation.distance(next.translation),
+                    current.translation.distance(next.translation),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn extrapolates_constant_motion() {
+        // A swing across the body while turning the wrist
+        let trace = record(20, |t| {
+            Transform::from_xyz(0.2 + 1.2 * t, 1.0, -0.3)
+                .with_rotation(Quat::from_rotation_y(2.0 * t))
+        });
+        let prediction = PosePrediction::default();
+        for window in trace.windows(3) {
+            let (linear_velocity, angular_velocity) = estimate_velocity(window[0], window[1], DT);
+            assert!(linear_velocity.abs_diff_eq(Vec3::new(1.2, 0.0, 0.0), 1e-3));
+            assert!(angular_velocity.abs_diff_eq(Vec3::new(0.0, 2.0, 0.0), 1e-3));
+
+            let predicted = prediction.predict(window[1], linear_velocity, angular_velocity, DT);
+            assert!(predicted
+                .translation
+                .abs_diff_eq(window[2].translation, 1e-4));
+            assert!(predicted.rotation.angle_between(window[2].rotation) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn reduces_lag_on_curved_motion() {
+        // Stirring in a circle, 30 cm across, about twice a second
+        let trace = record(72, |t| {
+            let angle = 12.0 * t;
+            Transform::from_xyz(0.15 * angle.cos(), 1.0, 0.15 * angle.sin())
+        });
+        for (predicted_error, measured_error) in
+            prediction_errors(&PosePrediction::default(), &trace)
+        {
+            assert!(predicted_error < measured_error * 0.25);
+        }
+    }
+
+    /// The controller trace in `testdata`, as the time and pose of each sample.
+    fn controller_trace() -> Vec<(f32, Transform)> {
+        include_str!("testdata/controller_trace.csv")
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+            .map(|line| {
+                let values: Vec<f32> = line
+                    .split(',')
+                    .map(|value| value.trim().parse().unwrap())
+                    .collect();
+                let rotation = Quat::from_xyzw(values[4], values[5], values[6], values[7]);
+                let pose = Transform::from_xyz(values[1], values[2], values[3])
+                    .with_rotation(rotation.normalize());
+                (values[0], pose)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reduces_lag_on_controller_trace() {
+        // Frames don't come exactly on time, so each step uses the trace's own timestamps
+        let prediction = PosePrediction::default();
+        let (mut predicted_error, mut measured_error) = (0.0, 0.0);
+        let (mut predicted_angle, mut measured_angle) = (0.0, 0.0);
+        for window in controller_trace().windows(3) {
+            let [(t0, previous), (t1, current), (t2, next)] = [window[0], window[1], window[2]];
+            let (linear_velocity, angular_velocity) = estimate_velocity(previous, current, t1 - t0);
+            let predicted = prediction.predict(current, linear_velocity, angular_velocity, t2 - t1);
+            predicted_error += predicted.translation.distance(next.translation);
+            measured_error += current.translation.distance(next.translation);
+            predicted_angle += predicted.rotation.angle_between(next.rotation);
+            measured_angle += current.rotation.angle_between(next.rotation);
+        }
+        // Noise gets extrapolated too, but the lag prediction takes away is far larger
+        assert!(predicted_error < measured_error * 0.3);
+        assert!(predicted_angle < measured_angle * 0.3);
+    }
+
+    #[test]
+    fn holds_still_when_stopped() {
+        let trace = record(10, |_| Transform::from_xyz(0.1, 1.2, -0.4));
+        for (predicted_error, _) in prediction_errors(&PosePrediction::default(), &trace) {
+            assert_eq!(predicted_error, 0.0);
+        }
+    }
+
+    #[test]
+    fn clamps_horizon() {
+        let prediction = PosePrediction {
+            max_horizon: 0.02,
+            ..default()
+        };
+        let predicted = prediction.predict(
+            Transform::IDENTITY,
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            0.5,
+        );
+        assert!(predicted
+            .translation
+            .abs_diff_eq(Vec3::new(0.02, 0.0, 0.0), 1e-6));
+        assert!((predicted.rotation.to_scaled_axis().z - 0.04).abs() < 1e-5);
+    }
+
+    #[test]
+    fn clamps_speed_on_tracking_glitch() {
+        // Tracking jumps half a meter and flips around for a single frame
+        let from = Transform::from_xyz(0.0, 1.0, 0.0);
+        let to = Transform::from_xyz(0.5, 1.0, 0.0).with_rotation(Quat::from_rotation_x(3.0));
+        let (linear_velocity, angular_velocity) = estimate_velocity(from, to, DT);
+        assert!(linear_velocity.length() > 30.0);
+
+        let prediction = PosePrediction::default();
+        let predicted = prediction.predict(to, linear_velocity, angular_velocity, DT);
+        let offset = predicted.translation - to.translation;
+        assert!((offset.length() - prediction.max_speed * DT).abs() < 1e-5);
+        assert!(offset.normalize().abs_diff_eq(Vec3::X, 1e-5));
+        let angle = predicted.rotation.angle_between(to.rotation);
+        assert!((angle - prediction.max_angular_speed * DT).abs() < 1e-4);
+    }
+
+    #[test]
+    fn takes_the_short_way_around() {
+        let from = Transform::from_rotation(Quat::from_rotation_z(0.1));
+        // The same rotation as a little further around, with the opposite sign
+        let to = Transform::from_rotation(-Quat::from_rotation_z(0.2));
+        let (_, angular_velocity) = estimate_velocity(from, to, DT);
+        assert!(angular_velocity.abs_diff_eq(Vec3::new(0.0, 0.0, 0.1 / DT), 1e-2));
+    }
+
+    #[test]
+    fn no_velocity_without_time() {
+        let to = Transform::from_xyz(1.0, 0.0, 0.0);
+        assert_eq!(
+            estimate_velocity(Transform::IDENTITY, to, 0.0),
+            (Vec3::ZERO, Vec3::ZERO)
+        );
+    }
+}
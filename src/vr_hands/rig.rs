@@ -0,0 +1,282 @@
+//! The player rig: a physics hand following each tracked controller, with a grab point and a
+//! ghost hand. Configure it with [`RigConfig`], and the rig is rebuilt whenever that changes.
+
+use bevy::prelude::*;
+use bevy_oxr::xr_input::{
+    trackers::{OpenXRController, OpenXRLeftController, OpenXRRightController, OpenXRTracker},
+    Hand,
+};
+use bevy_xpbd_3d::prelude::*;
+
+use super::{
+    detach::DespawnBody,
+    ghost_hand::GhostHand,
+    grabber::{Grabber, GrabberState},
+    pose_filter::PoseFilter,
+    pose_prediction::PosePrediction,
+    tracking_state::{TrackingLossBehavior, TrackingState},
+    velocity_tracking::{TeleportHeldObjects, TrackingDrive, VelocityTracked},
+};
+use crate::Layer;
+
+#[derive(Default)]
+pub struct XrRigPlugin {
+    /// The rig to start with, unless the app already inserted a [`RigConfig`].
+    pub config: RigConfig,
+}
+
+impl XrRigPlugin {
+    pub fn new(config: RigConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl Plugin for XrRigPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<RigConfig>() {
+            app.insert_resource(self.config.clone());
+        }
+        app.add_systems(Update, spawn_rig.run_if(resource_changed::<RigConfig>()));
+    }
+}
+
+/// Which hands the rig has, and how each is built. A hand that's `None` isn't spawned.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct RigConfig {
+    pub left: Option<HandConfig>,
+    pub right: Option<HandConfig>,
+}
+
+impl Default for RigConfig {
+    fn default() -> Self {
+        Self::both(HandConfig::default())
+    }
+}
+
+impl RigConfig {
+    /// Both hands, built the same way.
+    pub fn both(hand: HandConfig) -> Self {
+        Self {
+            left: Some(hand.clone()),
+            right: Some(hand),
+        }
+    }
+
+    pub fn hand(&self, hand: Hand) -> Option<&HandConfig> {
+        match hand {
+            Hand::Left => self.left.as_ref(),
+            Hand::Right => self.right.as_ref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandConfig {
+    pub shape: HandShape,
+    pub color: Color,
+    pub density: f32,
+    pub layers: CollisionLayers,
+    /// How the hand follows its controller. `follow_target` is replaced with the controller.
+    pub tracking: VelocityTracked,
+    /// Where the grab point is relative to the hand.
+    pub grab_point: Vec3,
+    pub search_radius: f32,
+    pub grab_tolerance: f32,
+    /// The collision layers the hand can grab objects on.
+    pub grabbable_layer_mask: u32,
+    /// Show a translucent copy of the hand at the controller when the physics hand gets blocked.
+    pub ghost_hand: bool,
+}
+
+impl Default for HandConfig {
+    fn default() -> Self {
+        Self {
+            shape: HandShape::Box(Vec3::new(0.1, 0.05, 0.1)),
+            color: Color::rgb(0.8, 0.7, 0.6),
+            density: 1000.0,
+            layers: CollisionLayers::new([Layer::Hand, Layer::Default], [Layer::Default]),
+            tracking: VelocityTracked {
+                follow_strength: 30.0,
+                max_distance: 0.75,
+                rotation_follow_strength: 30.0,
+                drive: TrackingDrive::Velocity,
+                on_teleport: TeleportHeldObjects::BringAlong,
+                on_tracking_lost: TrackingLossBehavior::Freeze,
+                filter: PoseFilter::one_euro(),
+                precision_filter: PoseFilter::precision(),
+                prediction: Some(PosePrediction::default()),
+                ..default()
+            },
+            grab_point: Vec3::new(0.0, -0.05, 0.0),
+            search_radius: 0.1,
+            grab_tolerance: 0.02,
+            grabbable_layer_mask: Layer::Grabbable.to_bits(),
+            ghost_hand: true,
+        }
+    }
+}
+
+/// The shape of a hand's mesh and collider.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandShape {
+    /// A box with the given full extents.
+    Box(Vec3),
+    Sphere {
+        radius: f32,
+    },
+    /// A capsule along the hand's Y axis, `height` being the length of its straight part.
+    Capsule {
+        radius: f32,
+        height: f32,
+    },
+}
+
+impl HandShape {
+    pub fn mesh(&self) -> Mesh {
+        match *self {
+            HandShape::Box(size) => shape::Box::new(size.x, size.y, size.z).into(),
+            HandShape::Sphere { radius } => shape::UVSphere {
+                radius,
+                ..default()
+            }
+            .into(),
+            HandShape::Capsule { radius, height } => shape::Capsule {
+                radius,
+                depth: height,
+                ..default()
+            }
+            .into(),
+        }
+    }
+
+    pub fn collider(&self) -> Collider {
+        match *self {
+            HandShape::Box(size) => Collider::cuboid(size.x, size.y, size.z),
+            HandShape::Sphere { radius } => Collider::ball(radius),
+            HandShape::Capsule { radius, height } => Collider::capsule(height, radius),
+        }
+    }
+}
+
+/// Marks the controllers and hands spawned for the rig, which are replaced when it changes.
+#[derive(Component)]
+pub struct RigPart;
+
+fn spawn_rig(
+    mut commands: Commands,
+    config: Res<RigConfig>,
+    parts: Query<Entity, With<RigPart>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // Hands may be holding something, so let go of it properly
+    for entity in parts.iter() {
+        commands.add(DespawnBody { body: entity });
+    }
+    for hand in [Hand::Left, Hand::Right] {
+        if let Some(hand_config) = config.hand(hand) {
+            spawn_hand(
+                &mut commands,
+                hand,
+                hand_config,
+                &mut meshes,
+                &mut materials,
+            );
+        }
+    }
+}
+
+/// Spawns a controller for `hand` and a physics hand following it, returning the hand.
+pub fn spawn_hand(
+    commands: &mut Commands,
+    hand: Hand,
+    config: &HandConfig,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) -> Entity {
+    let side = match hand {
+        Hand::Left => "Left",
+        Hand::Right => "Right",
+    };
+    let mut controller = commands.spawn((
+        OpenXRController,
+        OpenXRTracker,
+        TrackingState::default(),
+        SpatialBundle::default(),
+        Name::new(format!("{} Controller", side)),
+        RigPart,
+    ));
+    match hand {
+        Hand::Left => controller.insert(OpenXRLeftController),
+        Hand::Right => controller.insert(OpenXRRightController),
+    };
+    let controller = controller.id();
+
+    let hand_entity = commands
+        .spawn((
+            VelocityTracked {
+                follow_target: controller,
+                ..config.tracking
+            },
+            RigidBody::Dynamic,
+            ColliderDensity(config.density),
+            config.shape.collider(),
+            config.layers,
+            PbrBundle {
+                mesh: meshes.add(config.shape.mesh()),
+                material: materials.add(config.color.into()),
+                transform: Transform::from_xyz(0.0, 0.1, 0.0),
+                ..default()
+            },
+            Name::new(format!("{} Hand", side)),
+            RigPart,
+        ))
+        .with_children(|parent| {
+            // grab point
+            parent.spawn((
+                SpatialBundle::from_transform(Transform::from_translation(config.grab_point)),
+                Grabber {
+                    hand: hand.into(),
+                    search_radius: config.search_radius,
+                    grab_tolerance: config.grab_tolerance,
+                    grabbable_layer_mask: config.grabbable_layer_mask,
+                    state: GrabberState::Idle,
+                },
+                Name::new(format!("{} Grab Point", side)),
+            ));
+        })
+        .id();
+
+    if config.ghost_hand {
+        spawn_ghost_hand(commands, controller, hand_entity, config, meshes, materials);
+    }
+    hand_entity
+}
+
+// a translucent copy of the hand at the controller pose, shown when the physics hand gets blocked
+fn spawn_ghost_hand(
+    commands: &mut Commands,
+    controller: Entity,
+    hand: Entity,
+    config: &HandConfig,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) {
+    commands.entity(controller).with_children(|parent| {
+        parent.spawn((
+            GhostHand::new(hand),
+            PbrBundle {
+                mesh: meshes.add(config.shape.mesh()),
+                material: materials.add(StandardMaterial {
+                    base_color: config.color.with_a(0.0),
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    ..default()
+                }),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            Name::new("Ghost Hand"),
+        ));
+    });
+}
@@ -0,0 +1,93 @@
+use bevy::prelude::*;
+
+use super::velocity_tracking::{TrackingTarget, VelocityTracked};
+
+/// Whether a pose source is currently tracked. Input backends keep this up to date on the entities
+/// hands follow, and it's mirrored onto each [`VelocityTracked`] body.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Component)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrackingState {
+    #[default]
+    Tracked,
+    Lost,
+}
+
+/// What a [`VelocityTracked`] body does while its target has lost tracking. It always stops
+/// following the target, and picks up again where the target is once tracking resumes.
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrackingLossBehavior {
+    /// Stay where the hand was when tracking was lost.
+    #[default]
+    Freeze,
+    /// Hide the hand.
+    Hide,
+    /// Let go of held objects.
+    Release,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TrackingLost {
+    pub hand: Entity,
+    pub behavior: TrackingLossBehavior,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TrackingRegained {
+    pub hand: Entity,
+}
+
+pub(super) fn update_hand_tracking_states(
+    mut commands: Commands,
+    mut hands: Query<(
+        Entity,
+        &VelocityTracked,
+        Option<&mut TrackingState>,
+        Option<&mut Visibility>,
+    )>,
+    targets: Query<&TrackingState, Without<VelocityTracked>>,
+    mut lost_events: EventWriter<TrackingLost>,
+    mut regained_events: EventWriter<TrackingRegained>,
+) {
+    for (hand, track_config, hand_state, visibility) in hands.iter_mut() {
+        let target_state = targets
+            .get(track_config.follow_target)
+            .copied()
+            .unwrap_or_default();
+        let Some(mut hand_state) = hand_state else {
+            commands.entity(hand).insert(target_state);
+            continue;
+        };
+        if *hand_state == target_state {
+            continue;
+        }
+        *hand_state = target_state;
+
+        match target_state {
+            TrackingState::Lost => {
+                info!("Lost tracking for {:?}", hand);
+                if let (TrackingLossBehavior::Hide, Some(mut visibility)) =
+                    (track_config.on_tracking_lost, visibility)
+                {
+                    *visibility = Visibility::Hidden;
+                }
+                // Start over from the next valid pose instead of smoothing from the stale one
+                commands.entity(hand).remove::<TrackingTarget>();
+                lost_events.send(TrackingLost {
+                    hand,
+                    behavior: track_config.on_tracking_lost,
+                });
+            }
+            TrackingState::Tracked => {
+                info!("Regained tracking for {:?}", hand);
+                if let (TrackingLossBehavior::Hide, Some(mut visibility)) =
+                    (track_config.on_tracking_lost, visibility)
+                {
+                    *visibility = Visibility::Inherited;
+                }
+                regained_events.send(TrackingRegained { hand });
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*, utils::HashMap};
+use bevy_oxr::xr_input::Hand;
+
+use super::grabber::{Grabber, GrabberSet, GrabberState};
+use crate::input::{InputSet, InputState};
+
+pub struct UsablePlugin<L = Update> {
+    /// The schedule activation runs in, after [`GrabberSet`] there.
+    pub schedule: L,
+}
+
+impl<L: ScheduleLabel + Clone> UsablePlugin<L> {
+    pub fn new(schedule: L) -> Self {
+        Self { schedule }
+    }
+}
+
+impl Default for UsablePlugin {
+    fn default() -> Self {
+        Self::new(Update)
+    }
+}
+
+impl<L: ScheduleLabel + Clone> Plugin for UsablePlugin<L> {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Usable>()
+            .register_type::<UseControl>()
+            .add_event::<Activate>()
+            .add_systems(
+                self.schedule.clone(),
+                send_activate_events.after(InputSet).after(GrabberSet),
+            );
+    }
+}
+
+/// The control on the holding hand that activates a [`Usable`].
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum UseControl {
+    #[default]
+    Grip,
+    /// A on the right hand, X on the left.
+    PrimaryButton,
+    /// B on the right hand, Y on the left.
+    SecondaryButton,
+    ThumbstickClick,
+}
+
+/// A grabbable that sends [`Activate`] events while it is held.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default)]
+#[reflect(Component)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Usable {
+    pub control: UseControl,
+}
+
+/// Sent whenever the use control of a hand holding a [`Usable`] changes value, and with a value of
+/// zero when the usable is let go while active. Buttons report 0.0 or 1.0.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct Activate {
+    pub grabbable: Entity,
+    pub hand: Hand,
+    pub value: f32,
+}
+
+/// How far `control` is pressed on `hand`, from 0.0 to 1.0.
+pub fn control_value(input_state: &InputState, hand: Hand, control: UseControl) -> f32 {
+    let pressed = |pressed: bool| if pressed { 1.0 } else { 0.0 };
+    match (control, hand) {
+        (UseControl::Grip, Hand::Left) => input_state.left_grip.value,
+        (UseControl::Grip, Hand::Right) => input_state.right_grip.value,
+        (UseControl::PrimaryButton, Hand::Left) => pressed(input_state.x_button.pressed),
+        (UseControl::PrimaryButton, Hand::Right) => pressed(input_state.a_button.pressed),
+        (UseControl::SecondaryButton, Hand::Left) => pressed(input_state.y_button.pressed),
+        (UseControl::SecondaryButton, Hand::Right) => pressed(input_state.b_button.pressed),
+        (UseControl::ThumbstickClick, Hand::Left) => pressed(input_state.left_thumbstick.clicked),
+        (UseControl::ThumbstickClick, Hand::Right) => pressed(input_state.right_thumbstick.clicked),
+    }
+}
+
+/// Whether `grabber` holds a [`Usable`] bound to `control`. The control is the usable's then, so
+/// other bindings on it should leave it alone.
+pub fn uses_control(grabber: &Grabber, usables: &Query<&Usable>, control: UseControl) -> bool {
+    match grabber.state {
+        GrabberState::Grabbed(held, _) => usables
+            .get(held)
+            .is_ok_and(|usable| usable.control == control),
+        _ => false,
+    }
+}
+
+fn send_activate_events(
+    input_state: Res<InputState>,
+    grabbers: Query<(Entity, &Grabber)>,
+    usables: Query<&Usable>,
+    mut activate_events: EventWriter<Activate>,
+    // The usable each grabber was holding last frame, and the value last sent for it
+    mut last_sent: Local<HashMap<Entity, (Entity, f32)>>,
+) {
+    for (grabber_entity, grabber) in grabbers.iter() {
+        let held = match grabber.state {
+            GrabberState::Grabbed(entity, _) => usables.get(entity).ok().map(|usable| {
+                (
+                    entity,
+                    control_value(&input_state, grabber.hand.into(), usable.control),
+                )
+            }),
+            _ => None,
+        };
+
+        // Let go of the usable it was holding, release it if it was active
+        if let Some((last_grabbable, last_value)) = last_sent.get(&grabber_entity).copied() {
+            if held.map(|(grabbable, _)| grabbable) != Some(last_grabbable) {
+                if last_value != 0.0 {
+                    activate_events.send(Activate {
+                        grabbable: last_grabbable,
+                        hand: grabber.hand.into(),
+                        value: 0.0,
+                    });
+                }
+                last_sent.remove(&grabber_entity);
+            }
+        }
+
+        if let Some((grabbable, value)) = held {
+            let last_value = last_sent
+                .get(&grabber_entity)
+                .map_or(0.0, |(_, last_value)| *last_value);
+            if value != last_value {
+                activate_events.send(Activate {
+                    grabbable,
+                    hand: grabber.hand.into(),
+                    value,
+                });
+            }
+            last_sent.insert(grabber_entity, (grabbable, value));
+        }
+    }
+}
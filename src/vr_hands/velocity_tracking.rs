@@ -1,65 +1,423 @@
-use bevy::prelude::*;
+use bevy::{
+    ecs::{
+        entity::{EntityMapper, MapEntities},
+        reflect::ReflectMapEntities,
+        schedule::ScheduleLabel,
+    },
+    prelude::*,
+};
 use bevy_oxr::xr_input::trackers::update_open_xr_controllers;
-use bevy_xpbd_3d::prelude::*;
+use bevy_xpbd_3d::{plugins::setup::Physics, prelude::*, PhysicsSchedule, PhysicsStepSet};
 
-pub struct VelocityTrackingPlugin;
+use super::{
+    pose_filter::{PoseFilter, PoseFilterState, PrecisionMode},
+    pose_prediction::{estimate_velocity, PosePrediction, PredictionHorizon},
+    tracking_state::{
+        update_hand_tracking_states, TrackingLossBehavior, TrackingLost, TrackingRegained,
+        TrackingState,
+    },
+};
 
-impl Plugin for VelocityTrackingPlugin {
+/// The systems that drive [`VelocityTracked`] bodies towards their targets.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VelocityTrackingSet;
+
+pub struct VelocityTrackingPlugin<L = Update> {
+    /// The schedule [`VelocityTrackingSet`] runs in. In [`Update`] it's ordered after the OpenXR
+    /// controllers update and before physics, in any other schedule the app has to order it.
+    pub schedule: L,
+}
+
+impl<L: ScheduleLabel + Clone> VelocityTrackingPlugin<L> {
+    pub fn new(schedule: L) -> Self {
+        Self { schedule }
+    }
+}
+
+impl Default for VelocityTrackingPlugin {
+    fn default() -> Self {
+        Self::new(Update)
+    }
+}
+
+impl<L: ScheduleLabel + Clone> Plugin for VelocityTrackingPlugin<L> {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            velocity_track
-                // TODO make this configurable in the plugin
-                .after(update_open_xr_controllers)
-                .before(PhysicsSet::Prepare),
-        );
+        app.register_type::<VelocityTracked>()
+            .register_type::<TrackingDrive>()
+            .register_type::<PdDrive>()
+            .register_type::<TeleportHeldObjects>()
+            .register_type::<TrackingLossBehavior>()
+            .register_type::<TrackingState>()
+            .register_type::<PoseFilter>()
+            .register_type::<PosePrediction>()
+            .register_type::<Option<PosePrediction>>()
+            .register_type::<PredictionHorizon>()
+            .add_event::<HandTeleported>()
+            .add_event::<TrackingLost>()
+            .add_event::<TrackingRegained>()
+            .add_systems(
+                self.schedule.clone(),
+                (
+                    update_hand_tracking_states,
+                    update_tracking_targets,
+                    velocity_track,
+                )
+                    .chain()
+                    .in_set(VelocityTrackingSet),
+            );
+
+        app.get_schedule_mut(PhysicsSchedule)
+            .expect("add PhysicsSchedule first")
+            .add_systems(
+                drive_pd_controllers
+                    .after(PhysicsStepSet::BroadPhase)
+                    .before(PhysicsStepSet::Substeps),
+            );
+
+        if self.schedule.as_dyn_eq().dyn_eq(Update.as_dyn_eq()) {
+            app.configure_sets(
+                Update,
+                VelocityTrackingSet
+                    .after(update_open_xr_controllers)
+                    .before(PhysicsSet::Prepare),
+            );
+        }
     }
 }
 
-#[derive(Component, Debug, Clone, Copy)]
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component, MapEntities)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub struct VelocityTracked {
     pub follow_target: Entity,
     pub follow_strength: f32,
     pub max_distance: f32,
     pub rotation_follow_strength: f32,
+    pub drive: TrackingDrive,
+    pub on_teleport: TeleportHeldObjects,
+    pub on_tracking_lost: TrackingLossBehavior,
+    /// Smoothing applied to the target pose.
+    pub filter: PoseFilter,
+    /// Smoothing applied to the target pose while the body is in [`PrecisionMode`].
+    pub precision_filter: PoseFilter,
+    /// Drive towards where the target is predicted to be, rather than where it was measured.
+    pub prediction: Option<PosePrediction>,
+}
+
+impl Default for VelocityTracked {
+    fn default() -> Self {
+        Self {
+            follow_target: Entity::PLACEHOLDER,
+            follow_strength: 30.0,
+            max_distance: 0.75,
+            rotation_follow_strength: 30.0,
+            drive: TrackingDrive::default(),
+            on_teleport: TeleportHeldObjects::default(),
+            on_tracking_lost: TrackingLossBehavior::default(),
+            filter: PoseFilter::default(),
+            precision_filter: PoseFilter::default(),
+            prediction: None,
+        }
+    }
 }
 
+impl MapEntities for VelocityTracked {
+    fn map_entities(&mut self, entity_mapper: &mut EntityMapper) {
+        self.follow_target = entity_mapper.get_or_reserve(self.follow_target);
+    }
+}
+
+/// The pose a [`VelocityTracked`] body is driven towards, derived from its follow target.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct TrackingTarget {
+    /// The follow target's pose, as measured this frame and filtered.
+    pub measured: Transform,
+    /// The target's velocity, estimated from its recent poses.
+    pub linear_velocity: Vec3,
+    pub angular_velocity: Vec3,
+    /// The pose to drive towards.
+    pub pose: Transform,
+    filter_state: PoseFilterState,
+}
+
+/// What happens to held objects when a hand gets too far from its target and is teleported back.
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum TeleportHeldObjects {
+    /// Teleport held objects along with the hand, keeping their pose relative to it.
+    #[default]
+    BringAlong,
+    /// Let go of held objects where they are.
+    Release,
+}
+
+/// Sent when a [`VelocityTracked`] body gets farther than `max_distance` from its target and is
+/// snapped back onto it. Poses are in global space.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct HandTeleported {
+    pub hand: Entity,
+    pub from: Transform,
+    pub to: Transform,
+    pub held_objects: TeleportHeldObjects,
+}
+
+/// How a [`VelocityTracked`] body is moved towards its target.
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrackingDrive {
+    /// Set the body's velocities directly, proportional to the pose error and the follow strengths.
+    #[default]
+    Velocity,
+    /// Apply forces and torques from a proportional-derivative controller. The body has limited
+    /// strength, so it feels the mass of what it holds and gets blocked by what it pushes against.
+    PdController(PdDrive),
+}
+
+#[derive(Reflect, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct PdDrive {
+    /// Force per meter of position error, in N/m.
+    pub stiffness: f32,
+    /// Force per m/s of velocity, in N*s/m.
+    pub damping: f32,
+    pub max_force: f32,
+    /// Torque per radian of rotation error, in N*m/rad.
+    pub angular_stiffness: f32,
+    /// Torque per rad/s of angular velocity, in N*m*s/rad.
+    pub angular_damping: f32,
+    pub max_torque: f32,
+    /// Cancel out gravity on the tracked body itself, so only held objects weigh it down.
+    pub compensate_gravity: bool,
+}
+
+impl Default for PdDrive {
+    fn default() -> Self {
+        Self {
+            stiffness: 2000.0,
+            damping: 60.0,
+            max_force: 100.0,
+            angular_stiffness: 1.0,
+            angular_damping: 0.05,
+            max_torque: 5.0,
+            compensate_gravity: true,
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn update_tracking_targets(
+    mut commands: Commands,
+    mut tracked_objects: Query<(
+        Entity,
+        &VelocityTracked,
+        Option<&mut TrackingTarget>,
+        Has<PrecisionMode>,
+        Option<&TrackingState>,
+    )>,
+    targets: Query<&GlobalTransform>,
+    time: Res<Time>,
+    physics_time: Res<Time<Physics>>,
+) {
+    for (entity, track_config, tracking_target, precision_mode, tracking_state) in
+        tracked_objects.iter_mut()
+    {
+        if tracking_state == Some(&TrackingState::Lost) {
+            continue;
+        }
+
+        let measured = targets
+            .get(track_config.follow_target)
+            .unwrap()
+            .compute_transform();
+        let Some(mut tracking_target) = tracking_target else {
+            commands.entity(entity).insert(TrackingTarget {
+                measured,
+                pose: measured,
+                ..default()
+            });
+            continue;
+        };
+
+        let filter = match precision_mode {
+            true => track_config.precision_filter,
+            false => track_config.filter,
+        };
+        let measured = filter.apply(
+            &mut tracking_target.filter_state,
+            measured,
+            time.delta_seconds(),
+        );
+
+        let (linear_velocity, angular_velocity) =
+            estimate_velocity(tracking_target.measured, measured, time.delta_seconds());
+        tracking_target.measured = measured;
+        tracking_target.linear_velocity = linear_velocity;
+        tracking_target.angular_velocity = angular_velocity;
+
+        tracking_target.pose = match track_config.prediction {
+            Some(prediction) => {
+                let horizon = match prediction.horizon {
+                    PredictionHorizon::Seconds(seconds) => seconds,
+                    PredictionHorizon::PhysicsStep => physics_time.delta_seconds(),
+                };
+                prediction.predict(measured, linear_velocity, angular_velocity, horizon)
+            }
+            None => measured,
+        };
+    }
+}
+
+#[allow(clippy::type_complexity)]
 fn velocity_track(
     mut tracked_objects: Query<(
+        Entity,
         &VelocityTracked,
         &GlobalTransform,
         &mut Transform,
+        &mut Position,
+        &mut Rotation,
         &mut LinearVelocity,
         &mut AngularVelocity,
+        Option<&Parent>,
+        Option<&TrackingTarget>,
+        Option<&TrackingState>,
     )>,
     targets: Query<&GlobalTransform>,
+    mut teleported_events: EventWriter<HandTeleported>,
 ) {
     for (
+        entity,
         track_config,
         global_transform,
         mut local_transform,
+        mut position,
+        mut rotation,
         mut linear_velocity,
         mut angular_velocity,
+        parent,
+        tracking_target,
+        tracking_state,
     ) in tracked_objects.iter_mut()
     {
+        // Hold still until the target's pose can be trusted again
+        if tracking_state == Some(&TrackingState::Lost) {
+            linear_velocity.0 = Vec3::ZERO;
+            angular_velocity.0 = Vec3::ZERO;
+            continue;
+        }
+
         let global_transform = global_transform.compute_transform();
-        let target_transform = targets.get(track_config.follow_target).unwrap();
-        let target_transform = target_transform.compute_transform();
+        let target_transform = match tracking_target {
+            Some(tracking_target) => tracking_target.pose,
+            None => targets
+                .get(track_config.follow_target)
+                .unwrap()
+                .compute_transform(),
+        };
 
-        // Position tracking
         let delta_position = target_transform.translation - global_transform.translation;
-        if delta_position.length() <= track_config.max_distance {
-            linear_velocity.0 = (delta_position * track_config.follow_strength).into();
-        } else {
-            local_transform.translation =
-                global_transform.transform_point(target_transform.translation);
+        if delta_position.length() > track_config.max_distance {
+            // Too far behind to catch up, so snap the whole pose onto the target and start from rest
+            let target_local = match parent.and_then(|parent| targets.get(parent.get()).ok()) {
+                Some(parent_global) => {
+                    GlobalTransform::from(target_transform).reparented_to(parent_global)
+                }
+                None => target_transform,
+            };
+            local_transform.translation = target_local.translation;
+            local_transform.rotation = target_local.rotation;
+            position.0 = target_transform.translation;
+            rotation.0 = target_transform.rotation;
+            linear_velocity.0 = Vec3::ZERO;
+            angular_velocity.0 = Vec3::ZERO;
+
+            teleported_events.send(HandTeleported {
+                hand: entity,
+                from: global_transform,
+                to: target_transform,
+                held_objects: track_config.on_teleport,
+            });
+            continue;
+        }
+
+        // The PD controller drives with forces, once per physics step
+        if matches!(track_config.drive, TrackingDrive::Velocity) {
+            let delta_rotation =
+                shortest_rotation_between(global_transform.rotation, target_transform.rotation);
+            linear_velocity.0 = delta_position * track_config.follow_strength;
+            angular_velocity.0 =
+                delta_rotation.to_scaled_axis() * track_config.rotation_follow_strength;
         }
+    }
+}
 
-        // Rotation tracking
-        let delta_rotation =
-            shortest_rotation_between(global_transform.rotation, target_transform.rotation);
-        angular_velocity.0 =
-            delta_rotation.to_scaled_axis() * track_config.rotation_follow_strength;
+// Forces persist between physics steps, so they're set once per step rather than added to
+#[allow(clippy::type_complexity)]
+fn drive_pd_controllers(
+    mut tracked_objects: Query<(
+        &VelocityTracked,
+        &Position,
+        &Rotation,
+        &LinearVelocity,
+        &AngularVelocity,
+        &Mass,
+        &mut ExternalForce,
+        &mut ExternalTorque,
+        Option<&TrackingTarget>,
+        Option<&TrackingState>,
+    )>,
+    targets: Query<&GlobalTransform>,
+    gravity: Res<Gravity>,
+) {
+    for (
+        track_config,
+        position,
+        rotation,
+        linear_velocity,
+        angular_velocity,
+        mass,
+        mut external_force,
+        mut external_torque,
+        tracking_target,
+        tracking_state,
+    ) in tracked_objects.iter_mut()
+    {
+        let TrackingDrive::PdController(pd) = track_config.drive else {
+            continue;
+        };
+        let target_transform = match tracking_target {
+            Some(tracking_target) => Some(tracking_target.pose),
+            None => targets
+                .get(track_config.follow_target)
+                .ok()
+                .map(GlobalTransform::compute_transform),
+        };
+        let gravity_compensation = match pd.compensate_gravity {
+            true => -gravity.0 * mass.0,
+            false => Vec3::ZERO,
+        };
+        let (force, torque) = match target_transform {
+            Some(target_transform) if tracking_state != Some(&TrackingState::Lost) => {
+                let delta_position = target_transform.translation - position.0;
+                let delta_rotation =
+                    shortest_rotation_between(rotation.0, target_transform.rotation);
+                let force = delta_position * pd.stiffness - linear_velocity.0 * pd.damping
+                    + gravity_compensation;
+                let torque = delta_rotation.to_scaled_axis() * pd.angular_stiffness
+                    - angular_velocity.0 * pd.angular_damping;
+                (
+                    force.clamp_length_max(pd.max_force),
+                    torque.clamp_length_max(pd.max_torque),
+                )
+            }
+            // Still hold the hand up, so a frozen hand stays where it was
+            _ => (
+                gravity_compensation.clamp_length_max(pd.max_force),
+                Vec3::ZERO,
+            ),
+        };
+        external_force.set_force(force);
+        external_torque.set_torque(torque);
     }
 }
 